@@ -0,0 +1,126 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use happy_server::HappyServerError;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Name of the config file looked for in the distribution directory.
+pub const CONFIG_FILE_NAME: &str = "happy_server.toml";
+
+/// # Language written in the config file
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigLanguage {
+    Japanese,
+    English,
+}
+
+/// # Contents of `happy_server.toml`
+/// The keys are the long names of the command line arguments.
+/// Unknown keys are rejected, so that a typo does not go unnoticed.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub bind: Option<String>,
+    /// Either `port = 8080` or `port = "8080"`.
+    pub port: Option<toml::Value>,
+    pub dist_dir: Option<String>,
+    pub uri_prefix: Option<String>,
+    pub listen: Option<Vec<String>>,
+    pub auto_port: Option<bool>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub self_signed: Option<bool>,
+    pub cert_cache: Option<String>,
+    pub index: Option<Vec<String>>,
+    pub no_listing: Option<bool>,
+    pub listing_template: Option<String>,
+    pub spa: Option<bool>,
+    /// Relative to the distribution directory, like `--spa_index`.
+    pub spa_index: Option<String>,
+    pub spa_assets: Option<Vec<String>>,
+    pub upload: Option<bool>,
+    /// e.g. "100M", like `--upload_max_size`.
+    pub upload_max_size: Option<String>,
+    /// e.g. "500M", like `--upload_max_request_size`.
+    pub upload_max_request_size: Option<String>,
+    pub upload_overwrite: Option<String>,
+    pub webdav: Option<bool>,
+    pub webdav_write: Option<bool>,
+    /// "user:password" entries, like `--auth`.
+    pub auth: Option<Vec<String>>,
+    pub htpasswd: Option<String>,
+    /// CIDR ranges, like `--allow` and `--deny`.
+    pub allow: Option<Vec<String>>,
+    pub deny: Option<Vec<String>>,
+    pub lan_only: Option<bool>,
+    /// Relative to the distribution directory, like `--share`.
+    pub share: Option<Vec<String>>,
+    /// e.g. "24h", like `--share_expires`.
+    pub share_expires: Option<String>,
+    pub share_max_downloads: Option<u32>,
+    pub share_secret: Option<String>,
+    /// "path=url" rules, like `--proxy`.
+    pub proxy: Option<Vec<String>>,
+    pub compress: Option<bool>,
+    /// Content types such as "text/*", like `--compress_include` and `--compress_exclude`.
+    pub compress_include: Option<Vec<String>>,
+    pub compress_exclude: Option<Vec<String>>,
+    /// e.g. "1K", like `--compress_min_size`.
+    pub compress_min_size: Option<String>,
+    pub precompressed: Option<bool>,
+    pub watch: Option<bool>,
+    /// "common", "combined" or "json", like `--access_log`.
+    pub access_log: Option<String>,
+    pub access_log_file: Option<String>,
+    /// e.g. "100M", like `--access_log_max_size`.
+    pub access_log_max_size: Option<String>,
+    pub access_log_daily: Option<bool>,
+    pub access_log_compress: Option<bool>,
+    pub access_log_max_files: Option<usize>,
+    pub qr: Option<bool>,
+    pub language: Option<ConfigLanguage>,
+    pub color: Option<bool>,
+    pub clipboard: Option<bool>,
+    pub copy_url: Option<String>,
+}
+
+impl ConfigFile {
+    /// # Read the config file
+    /// Relative paths in the file are resolved against the directory of the file,
+    /// so that the same file works wherever happy server is started.
+    pub fn read(path: &Path) -> Result<Self, HappyServerError> {
+        let contents = std::fs::read_to_string(path).map_err(|e| HappyServerError::ConfigFile(path.to_path_buf(), e))?;
+        let mut config: Self = toml::from_str(&contents).map_err(|e| HappyServerError::ConfigParse(path.to_path_buf(), e))?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for relative_path in [&mut config.dist_dir, &mut config.cert, &mut config.key, &mut config.cert_cache, &mut config.listing_template, &mut config.htpasswd, &mut config.access_log_file].iter_mut() {
+            if let Some(relative_path) = relative_path.as_mut() {
+                *relative_path = base.join(relative_path.as_str()).display().to_string();
+            }
+        }
+        Ok(config)
+    }
+    /// # The port as written, to be validated with the other parameters
+    pub fn port(&self) -> Option<String> {
+        match &self.port {
+            Some(toml::Value::String(port)) => Some(port.clone()),
+            Some(port) => Some(port.to_string()),
+            None => None,
+        }
+    }
+}
+
+/// # Find the config file
+/// The file given with `--config` must exist,
+/// otherwise `happy_server.toml` in the distribution directory is used if there is one.
+/// The server refuses to deliver the file it was configured with.
+pub fn locate(config: Option<String>, distribution_dir: Option<&str>) -> Option<PathBuf> {
+    match config {
+        Some(path) => Some(PathBuf::from(path)),
+        None => {
+            let path = Path::new(distribution_dir.unwrap_or(".")).join(CONFIG_FILE_NAME);
+            if path.is_file() {Some(path)} else {None}
+        }
+    }
+}
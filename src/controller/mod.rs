@@ -1,16 +1,63 @@
 // Copyright (c) 2021 Daichi Aoki  
 // Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
 
-use super::*;
+mod config_file;
+
+use happy_server::{server_core, Language};
 
 use clap::*;
-use super::model::HappyServerModel;
+use happy_server::model::{HappyServerModel, ParameterSource, ENV_PREFIX};
+use config_file::{ConfigFile, ConfigLanguage};
+
+/// # Read an environment variable
+/// `name` is the part after `HAPPY_SERVER_`. Empty values are treated as unset.
+fn env_var(name: &str) -> Option<String> {
+    match std::env::var(format!("{}{}", ENV_PREFIX, name)) {
+        Ok(value) if !value.is_empty() => Some(value),
+        _ => None,
+    }
+}
+
+/// # Read an on/off environment variable
+/// "0", "false", "no" and "off" turn the option off, any other value turns it on.
+fn env_flag(name: &str) -> Option<bool> {
+    env_var(name).map(|value| !matches!(value.to_lowercase().as_str(), "0" | "false" | "no" | "off"))
+}
+
+/// # Split a comma separated list
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect()
+}
+
+/// # Pick the value with the highest precedence
+/// command line argument > environment variable > config file
+fn layered<T>(cli_arg: Option<T>, env: Option<T>, config_file: Option<T>) -> Option<ParameterSource<T>> {
+    match (cli_arg, env, config_file) {
+        (Some(value), _, _) => Some(ParameterSource::CliArg(value)),
+        (None, Some(value), _) => Some(ParameterSource::Env(value)),
+        (None, None, Some(value)) => Some(ParameterSource::ConfigFile(value)),
+        (None, None, None) => None,
+    }
+}
+
+/// # Pick the value of an on/off option
+/// A flag on the command line can only turn the option on.
+fn layered_flag(cli_arg: bool, env: Option<bool>, config_file: Option<bool>) -> ParameterSource<bool> {
+    match layered(if cli_arg {Some(true)} else {None}, env, config_file) {
+        Some(flag) => flag,
+        None => ParameterSource::Default(false),
+    }
+}
+
+/// # Read the parameters from the command line arguments
+/// Parameters that are not given on the command line are read from
+/// the `HAPPY_SERVER_*` environment variables, then from the config file.
 pub struct CliArgGetter{
     pub language: super::Language
 }
 
 impl CliArgGetter {
-    pub fn get_arguments(&self) -> (Language, bool, Option<bool>, HappyServerModel){
+    pub fn get_arguments(&self) -> (Language, bool, Option<bool>, Option<String>, bool, HappyServerModel){
         // accept command line arguments
         let mut args = app_from_crate!()
         .arg(Arg::with_name("port")
@@ -22,6 +69,34 @@ impl CliArgGetter {
             .long("port")
             .value_name("port_number")
             .takes_value(true))
+        .arg(Arg::with_name("auto_port")
+            .help(match self.language {
+                Language::Japanese => "ポートが使用中の場合、空いているポートが見つかるまで次の番号を試します(-p 0 でOSが空きポートを選びます)",
+                Language::English => "If the port is in use, try the next port numbers until a free one is found (-p 0 lets the OS choose a free port)",
+            })
+            .long("auto_port")
+            .alias("auto-port"))
+        .arg(Arg::with_name("bind")
+            .help(match self.language {
+                Language::Japanese => "待ち受けアドレスの指定 ... IPv4/IPv6アドレスまたはホスト名。127.0.0.1 でこのPCからのみ、:: でIPv4とIPv6の両方から接続できます。",
+                Language::English => "Specify the address to listen on ... an IPv4/IPv6 address or a host name. 127.0.0.1 allows only this machine, :: allows both IPv4 and IPv6.",
+            })
+            .short("b")
+            .long("bind")
+            .visible_alias("host")
+            .value_name("address")
+            .takes_value(true))
+        .arg(Arg::with_name("listen")
+            .help(match self.language {
+                Language::Japanese => "追加の待ち受け先の指定(複数可) ... 8080、https://[::]:8443、unix:/tmp/happy_server.sock など。-p と -b を指定しなければ、これだけで待ち受けます。",
+                Language::English => "Specify additional addresses to listen on (repeatable) ... e.g. 8080, https://[::]:8443, unix:/tmp/happy_server.sock. Without -p and -b, only these are used.",
+            })
+            .short("l")
+            .long("listen")
+            .value_name("listen address")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
         .arg(Arg::with_name("uri_prefix")
             .help(match self.language {
                 Language::Japanese => "配信uriの指定 ... https://localhost/〇〇/index.html の〇〇の部分です。",
@@ -39,7 +114,311 @@ impl CliArgGetter {
             .short("d")
             .long("dist_dir")
             .value_name("distribution directory")
+            .takes_value(true))
+        .arg(Arg::with_name("index")
+            .help(match self.language {
+                Language::Japanese => "ディレクトリにアクセスされた時に返すファイル名の指定(カンマ区切り) ... 値を省略すると index.html,index.htm です。",
+                Language::English => "Specify the file names returned when a directory is requested (comma separated) ... Without a value, index.html,index.htm.",
+            })
+            .long("index")
+            .value_name("file names")
+            .takes_value(true)
+            .min_values(0)
+            .max_values(1))
+        .arg(Arg::with_name("no_listing")
+            .help(match self.language {
+                Language::Japanese => "ディレクトリの一覧を表示しません ... インデックスファイルが無いディレクトリには403を返します。",
+                Language::English => "Do not list the files of directories ... directories without an index file return 403.",
+            })
+            .long("no_listing")
+            .alias("no-listing"))
+        .arg(Arg::with_name("listing_template")
+            .help(match self.language {
+                Language::Japanese => "ディレクトリ一覧のHTMLテンプレートファイルの指定 ... {{lang}}、{{title}}、{{breadcrumbs}}、{{table}} が置き換えられます。",
+                Language::English => "Specify an HTML template file for the directory listing ... {{lang}}, {{title}}, {{breadcrumbs}} and {{table}} are replaced.",
+            })
+            .long("listing_template")
+            .value_name("template file")
+            .takes_value(true)
+            .conflicts_with("no_listing"))
+        .arg(Arg::with_name("spa")
+            .help(match self.language {
+                Language::Japanese => "シングルページアプリケーションとして配信します ... ファイルが無いパスには index.html を返します。",
+                Language::English => "Deliver a single-page application ... paths that are not files receive index.html.",
+            })
+            .long("spa"))
+        .arg(Arg::with_name("spa_index")
+            .help(match self.language {
+                Language::Japanese => "シングルページアプリケーションのインデックスファイルの指定(配信ディレクトリからの相対パス)",
+                Language::English => "Specify the index file of the single-page application (relative to the distribution directory)",
+            })
+            .long("spa_index")
+            .value_name("index file")
+            .takes_value(true)
+            .requires("spa"))
+        .arg(Arg::with_name("spa_assets")
+            .help(match self.language {
+                Language::Japanese => "インデックスファイルの代わりに404を返す拡張子の指定(カンマ区切り、例: js,css,png)",
+                Language::English => "Specify the extensions that return 404 instead of the index file (comma separated, e.g. js,css,png)",
+            })
+            .long("spa_assets")
+            .value_name("extensions")
+            .takes_value(true)
+            .requires("spa"))
+        .arg(Arg::with_name("upload")
+            .help(match self.language {
+                Language::Japanese => "ファイルのアップロードを受け付けます ... 一覧のフォームか PUT で配信ディレクトリに保存します。",
+                Language::English => "Accept file uploads ... files from the form of the listing or PUT requests are saved in the distribution directory.",
+            })
+            .long("upload"))
+        .arg(Arg::with_name("upload_max_size")
+            .help(match self.language {
+                Language::Japanese => "アップロードできるファイルの最大サイズの指定(例: 100M、2G) ... 指定しなければ1Gです。",
+                Language::English => "Specify the largest file that can be uploaded (e.g. 100M, 2G) ... 1G by default.",
+            })
+            .long("upload_max_size")
+            .value_name("size")
+            .takes_value(true)
+            .requires("upload"))
+        .arg(Arg::with_name("upload_max_request_size")
+            .help(match self.language {
+                Language::Japanese => "フォームから一度に送れるデータの最大サイズの指定(例: 500M、8G) ... 指定しなければ4Gです。",
+                Language::English => "Specify the largest form post, with all its files (e.g. 500M, 8G) ... 4G by default.",
+            })
+            .long("upload_max_request_size")
+            .value_name("size")
+            .takes_value(true)
+            .requires("upload"))
+        .arg(Arg::with_name("upload_overwrite")
+            .help(match self.language {
+                Language::Japanese => "同じ名前のファイルがある時の動作の指定 ... reject(拒否、既定)、rename(名前を変えて保存)、replace(置き換え)",
+                Language::English => "Specify what to do when a file with the same name exists ... reject (default), rename (save under another name) or replace",
+            })
+            .long("upload_overwrite")
+            .value_name("policy")
+            .takes_value(true)
+            .possible_values(&["reject", "rename", "replace"])
+            .requires("upload"))
+        .arg(Arg::with_name("webdav")
+            .help(match self.language {
+                Language::Japanese => "WebDAVで配信します ... ファイルマネージャーからネットワークドライブとして接続できます。(読み取り専用)",
+                Language::English => "Deliver via WebDAV ... file managers can mount the directory as a network drive. (read-only)",
+            })
+            .long("webdav"))
+        .arg(Arg::with_name("webdav_write")
+            .help(match self.language {
+                Language::Japanese => "WebDAVでのファイルの作成、変更、削除を許可します",
+                Language::English => "Allow WebDAV clients to create, change and delete files",
+            })
+            .long("webdav_write")
+            .alias("webdav-write")
+            .requires("webdav"))
+        .arg(Arg::with_name("auth")
+            .help(match self.language {
+                Language::Japanese => "ベーシック認証のユーザーの指定(user:password、複数指定可) ... パスワードには bcrypt か argon2 のハッシュも使えます。",
+                Language::English => "Specify a user for basic authentication (user:password, repeatable) ... the password can also be a bcrypt or argon2 hash.",
+            })
+            .long("auth")
+            .value_name("user:password")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("htpasswd")
+            .help(match self.language {
+                Language::Japanese => "ベーシック認証のユーザーを htpasswd ファイルから読み込みます(bcrypt、argon2)",
+                Language::English => "Read the users for basic authentication from an htpasswd file (bcrypt, argon2)",
+            })
+            .long("htpasswd")
+            .value_name("htpasswd file")
+            .takes_value(true))
+        .arg(Arg::with_name("allow")
+            .help(match self.language {
+                Language::Japanese => "アクセスを許可するアドレスの範囲の指定(CIDR形式、例: 192.168.0.0/16、複数指定可) ... 範囲外のクライアントは拒否されます。",
+                Language::English => "Specify an address range allowed to access (CIDR, e.g. 192.168.0.0/16, repeatable) ... other clients are rejected.",
+            })
+            .long("allow")
+            .value_name("cidr")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("deny")
+            .help(match self.language {
+                Language::Japanese => "アクセスを拒否するアドレスの範囲の指定(CIDR形式、複数指定可) ... --allow より優先されます。",
+                Language::English => "Specify an address range denied access (CIDR, repeatable) ... takes precedence over --allow.",
+            })
+            .long("deny")
+            .value_name("cidr")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("lan_only")
+            .help(match self.language {
+                Language::Japanese => "ローカルネットワーク(ループバック、プライベートアドレス、リンクローカル)からのアクセスのみ許可します",
+                Language::English => "Allow access only from the local network (loopback, private and link-local addresses)",
+            })
+            .long("lan_only")
+            .alias("lan-only"))
+        .arg(Arg::with_name("share")
+            .help(match self.language {
+                Language::Japanese => "ファイルを署名付きの共有リンクで配信します(配信ディレクトリからの相対パス、複数指定可) ... リンクのないリクエストは拒否されます。",
+                Language::English => "Share a file with a signed link (relative to the distribution directory, repeatable) ... requests without a valid link are rejected.",
+            })
+            .long("share")
+            .value_name("file")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("share_expires")
+            .help(match self.language {
+                Language::Japanese => "共有リンクの有効期間の指定(例: 90s、30m、7d) ... 指定しなければ24hです。",
+                Language::English => "Specify how long the share links are valid (e.g. 90s, 30m, 7d) ... 24h by default.",
+            })
+            .long("share_expires")
+            .value_name("duration")
+            .takes_value(true)
+            .requires("share"))
+        .arg(Arg::with_name("share_max_downloads")
+            .help(match self.language {
+                Language::Japanese => "共有リンクでダウンロードできる回数の指定 ... 範囲指定のリクエストも1回と数えます。",
+                Language::English => "Specify how many times a share link can be downloaded ... a request for a range counts as a download as well.",
+            })
+            .long("share_max_downloads")
+            .value_name("count")
+            .takes_value(true)
+            .requires("share"))
+        .arg(Arg::with_name("share_secret")
+            .help(match self.language {
+                Language::Japanese => "共有リンクの署名の鍵の指定 ... 指定すると、再起動しても以前のリンクが使えます。指定しなければランダムな鍵を使います。",
+                Language::English => "Specify the key that signs the share links ... with it, links stay valid across restarts. Without it, a random key is used.",
+            })
+            .long("share_secret")
+            .value_name("secret")
+            .takes_value(true))
+        .arg(Arg::with_name("proxy")
+            .help(match self.language {
+                Language::Japanese => "パス以下のリクエストを別のサーバーに転送します(例: /api=http://127.0.0.1:3000、複数指定可) ... WebSocket も転送します。",
+                Language::English => "Forward the requests below a path to another server (e.g. /api=http://127.0.0.1:3000, repeatable) ... WebSockets included.",
+            })
+            .long("proxy")
+            .value_name("path=url")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("watch")
+            .help(match self.language {
+                Language::Japanese => "配信ディレクトリの変更を監視し、ブラウザを自動で再読み込みします ... CSSだけの変更は再読み込みせずに反映します。",
+                Language::English => "Watch the distribution directory and reload the browsers on changes ... CSS changes are applied without reloading.",
+            })
+            .long("watch"))
+        .arg(Arg::with_name("compress")
+            .help(match self.language {
+                Language::Japanese => "応答をブラウザが対応する形式(brotli、zstd、gzip)で圧縮します",
+                Language::English => "Compress the responses in a format the browser accepts (brotli, zstd, gzip)",
+            })
+            .long("compress"))
+        .arg(Arg::with_name("compress_include")
+            .help(match self.language {
+                Language::Japanese => "圧縮するContent-Typeの指定(例: text/*、application/json、複数指定可) ... 指定しなければテキスト、JavaScript、JSON、SVGなどです。",
+                Language::English => "Specify a content type to compress (e.g. text/*, application/json, repeatable) ... text, JavaScript, JSON, SVG and the like by default.",
+            })
+            .long("compress_include")
+            .value_name("content type")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .requires("compress"))
+        .arg(Arg::with_name("compress_exclude")
+            .help(match self.language {
+                Language::Japanese => "圧縮しないContent-Typeの指定(複数指定可) ... --compress_include より優先されます。text/event-stream は常に圧縮しません。",
+                Language::English => "Specify a content type not to compress (repeatable) ... takes precedence over --compress_include. text/event-stream is never compressed.",
+            })
+            .long("compress_exclude")
+            .value_name("content type")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .requires("compress"))
+        .arg(Arg::with_name("compress_min_size")
+            .help(match self.language {
+                Language::Japanese => "圧縮する応答の最小サイズの指定(例: 1K) ... 指定しなければ1Kです。",
+                Language::English => "Specify the smallest response to compress (e.g. 1K) ... 1K by default.",
+            })
+            .long("compress_min_size")
+            .value_name("size")
+            .takes_value(true)
+            .requires("compress"))
+        .arg(Arg::with_name("precompressed")
+            .help(match self.language {
+                Language::Japanese => "ファイルの隣に圧縮済みのファイル(.br、.zst、.gz)があれば、ブラウザが対応する場合にそれを配信します",
+                Language::English => "Deliver the precompressed file next to the requested one (.br, .zst, .gz) if the browser accepts it",
+            })
+            .long("precompressed"))
+        .arg(Arg::with_name("access_log")
+            .help(match self.language {
+                Language::Japanese => "リクエストごとにアクセスログを出力します(common、combined、json) ... 応答時間、送信バイト数、ステータス、クライアントを含みます。",
+                Language::English => "Log each request (common, combined or json) ... with the latency, bytes sent, status and client.",
+            })
+            .long("access_log")
+            .alias("access-log")
+            .value_name("format")
+            .takes_value(true)
+            .possible_values(&["common", "combined", "json"]))
+        .arg(Arg::with_name("access_log_file")
+            .help(match self.language {
+                Language::Japanese => "アクセスログを書き込むファイルの指定 ... 指定しなければ標準出力に出力します。",
+                Language::English => "Specify the file the access log is appended to ... Without it, the log is printed to stdout.",
+            })
+            .long("access_log_file")
+            .alias("access-log-file")
+            .value_name("log file")
+            .takes_value(true)
+            .requires("access_log"))
+        .arg(Arg::with_name("access_log_max_size")
+            .help(match self.language {
+                Language::Japanese => "アクセスログのファイルがこのサイズを超える前に切り替えます(例: 100M)",
+                Language::English => "Rotate the access log file before it grows larger than this size (e.g. 100M)",
+            })
+            .long("access_log_max_size")
+            .value_name("size")
+            .takes_value(true)
+            .requires("access_log_file"))
+        .arg(Arg::with_name("access_log_daily")
+            .help(match self.language {
+                Language::Japanese => "アクセスログのファイルを日ごと(UTC)に切り替えます",
+                Language::English => "Rotate the access log file every day (UTC)",
+            })
+            .long("access_log_daily")
+            .requires("access_log_file"))
+        .arg(Arg::with_name("access_log_compress")
+            .help(match self.language {
+                Language::Japanese => "切り替えたアクセスログのファイルを gzip で圧縮します",
+                Language::English => "Compress the rotated access log files with gzip",
+            })
+            .long("access_log_compress")
+            .requires("access_log_file"))
+        .arg(Arg::with_name("access_log_max_files")
+            .help(match self.language {
+                Language::Japanese => "残す切り替え済みのアクセスログのファイルの数 ... 古いものから削除されます。指定しなければすべて残します。",
+                Language::English => "Specify how many rotated access log files are kept ... the oldest are deleted. All are kept by default.",
+            })
+            .long("access_log_max_files")
+            .value_name("count")
+            .takes_value(true)
+            .requires("access_log_file"))
+        .arg(Arg::with_name("config")
+            .help(match self.language {
+                Language::Japanese => "設定ファイル(TOML形式)の指定 ... 指定しなければ、配信ディレクトリの happy_server.toml を読み込みます(このファイルは配信されません)。",
+                Language::English => "Specify the config file (TOML format) ... Without it, happy_server.toml in the distribution directory is read (and never delivered).",
+            })
+            .long("config")
+            .value_name("config file")
             .takes_value(true));
+        args = args.arg(Arg::with_name("qr")
+            .help(match self.language {
+                Language::Japanese => "同じネットワークのスマートフォンから開けるように、URLのQRコードを表示します",
+                Language::English => "Show a QR code of the URL so that phones on the same network can open it",
+            })
+            .long("qr"));
         args = match self.language {
             Language::Japanese => args.arg(Arg::with_name("english")
             .help("標準出力を英語にします")
@@ -51,6 +430,42 @@ impl CliArgGetter {
             .long("japanese"))
         };
 
+        args = if cfg!(not(feature = "no_ssl")) {
+            args.arg(Arg::with_name("server_crt")
+                .help(match self.language {
+                    Language::Japanese => "httpsで配信する場合の証明書ファイル(PEM形式)の指定",
+                    Language::English => "Specify the certificate file (PEM format) to deliver via https",
+                })
+                .long("cert")
+                .value_name("certificate file")
+                .takes_value(true))
+            .arg(Arg::with_name("server_key")
+                .help(match self.language {
+                    Language::Japanese => "httpsで配信する場合の秘密鍵ファイル(PEM形式)の指定",
+                    Language::English => "Specify the private key file (PEM format) to deliver via https",
+                })
+                .long("key")
+                .value_name("private key file")
+                .takes_value(true))
+            .arg(Arg::with_name("self_signed")
+                .help(match self.language {
+                    Language::Japanese => "自己署名証明書をその場で生成して、httpsで配信します",
+                    Language::English => "Generate a self-signed certificate on the fly and deliver via https",
+                })
+                .long("self_signed")
+                .alias("self-signed")
+                .conflicts_with_all(&["server_crt", "server_key"]))
+            .arg(Arg::with_name("cert_cache")
+                .help(match self.language {
+                    Language::Japanese => "生成した自己署名証明書を保存して再利用するディレクトリの指定",
+                    Language::English => "Specify a directory to save and reuse the generated self-signed certificate",
+                })
+                .long("cert_cache")
+                .value_name("cache directory")
+                .takes_value(true)
+                .requires("self_signed"))
+        }else {args};
+
         args = if cfg!(not(feature = "no_clipboard")) {
             args.arg(Arg::with_name("no_clipboard")
                 .help(match self.language {
@@ -58,6 +473,15 @@ impl CliArgGetter {
                     Language::English => "Do not use the clipboard function.",
                 })
                 .long("no_clipboard"))
+            .arg(Arg::with_name("copy_url")
+                .help(match self.language {
+                    Language::Japanese => "クリップボードにコピーするURLを、ネットワークインターフェース名またはIPアドレスで指定します(例: eth0、192.168.0.10)",
+                    Language::English => "Select the URL to copy to the clipboard by network interface name or IP address (e.g. eth0, 192.168.0.10)",
+                })
+                .long("copy_url")
+                .value_name("interface")
+                .takes_value(true)
+                .conflicts_with("no_clipboard"))
         }else {args};
 
         args = if cfg!(feature = "no_color") {
@@ -80,53 +504,120 @@ impl CliArgGetter {
         // Parse the arguments
         let matches = args.get_matches();
 
-        let color = if cfg!(feature = "no_color") {
-            match matches.occurrences_of("color") {
-                0 => false,
-                _ => true,
-            }
-        } else {
-            match matches.occurrences_of("no_color") {
-                0 => true,
-                _ => false,
-            }
+        let cli_distribution_dir = matches.value_of_lossy("distribution_dir").map(|path| path.to_string());
+        let env_distribution_dir = env_var("DIST_DIR");
+        let config_path = config_file::locate(
+            matches.value_of_lossy("config").map(|path| path.to_string()).or_else(|| env_var("CONFIG")),
+            cli_distribution_dir.as_deref().or(env_distribution_dir.as_deref()));
+        let (config, config_file) = match config_path {
+            Some(path) => match ConfigFile::read(&path) {
+                Ok(config) => (config, Some(Ok(path))),
+                Err(e) => (ConfigFile::default(), Some(Err(e))),
+            },
+            None => (ConfigFile::default(), None),
+        };
+
+        let config_port = config.port();
+        let color = match (cfg!(feature = "no_color"), matches.occurrences_of("color"), matches.occurrences_of("no_color")) {
+            (true, 0, _) | (false, _, 0) => env_flag("COLOR").or(config.color).unwrap_or(!cfg!(feature = "no_color")),
+            (true, _, _) => true,
+            (false, _, _) => false,
         };
 
         let using_clipboard = if cfg!(not(feature = "no_clipboard")) {
             match matches.occurrences_of("no_clipboard") {
-                0 => Some(true),
+                0 => Some(env_flag("CLIPBOARD").or(config.clipboard).unwrap_or(true)),
                 _ => Some(false)
             }
         }else {None};
 
-        let language = match self.language {
-            Language::Japanese => match matches.occurrences_of("english") {
-                0 => Language::Japanese,
-                _ => Language::English,
+        let language = match (&self.language, matches.occurrences_of("english"), matches.occurrences_of("japanese")) {
+            (Language::Japanese, 0, _) | (Language::English, _, 0) => {
+                let env_language = env_var("LANGUAGE").and_then(|language| match language.to_lowercase().as_str() {
+                    "japanese" | "ja" => Some(ConfigLanguage::Japanese),
+                    "english" | "en" => Some(ConfigLanguage::English),
+                    _ => None,
+                });
+                match env_language.or(config.language) {
+                    Some(ConfigLanguage::Japanese) => Language::Japanese,
+                    Some(ConfigLanguage::English) => Language::English,
+                    None => match self.language {
+                        Language::Japanese => Language::Japanese,
+                        Language::English => Language::English,
+                    },
+                }
             },
-            Language::English => match matches.occurrences_of("japanese") {
-                0 => Language::English,
-                _ => Language::Japanese
-            }
+            (Language::Japanese, _, _) => Language::English,
+            (Language::English, _, _) => Language::Japanese,
         };
 
+        let clipboard_url = matches.value_of_lossy("copy_url").map(|selector| selector.to_string())
+            .or_else(|| env_var("COPY_URL"))
+            .or(config.copy_url);
+        let qr_code = *layered_flag(matches.occurrences_of("qr") != 0, env_flag("QR"), config.qr).get_ref();
+
         // return value
-        (language, color, using_clipboard, 
+        (language, color, using_clipboard, clipboard_url, qr_code,
             HappyServerModel{
-                port: match matches.value_of_lossy("port") { 
-                    Some(port) => Some(port.to_string()),
-                    None => None
-                },
-                distribution_dir: match matches.value_of_lossy("distribution_dir") {
-                    Some(path) => Some(path.to_string()),
-                    None => None
-                },
-                uri_prefix: match matches.value_of_lossy("uri_prefix"){
-                    Some(uri_prefix) => Some(uri_prefix.to_string()),
-                    None => None
-                },
+                bind: layered(matches.value_of_lossy("bind").map(|host| host.to_string()), env_var("BIND"), config.bind),
+                port: layered(matches.value_of_lossy("port").map(|port| port.to_string()), env_var("PORT"), config_port),
+                distribution_dir: layered(cli_distribution_dir, env_distribution_dir, config.dist_dir),
+                uri_prefix: layered(matches.value_of_lossy("uri_prefix").map(|uri_prefix| uri_prefix.to_string()), env_var("URI_PREFIX"), config.uri_prefix),
+                listen: layered(
+                    matches.values_of_lossy("listen"),
+                    env_var("LISTEN").map(|listen| split_list(&listen)),
+                    config.listen),
+                auto_port: layered_flag(matches.occurrences_of("auto_port") != 0, env_flag("AUTO_PORT"), config.auto_port),
+                server_crt: layered(matches.value_of_lossy("server_crt").map(|path| path.to_string()), env_var("CERT"), config.cert),
+                server_key: layered(matches.value_of_lossy("server_key").map(|path| path.to_string()), env_var("KEY"), config.key),
+                self_signed: layered_flag(matches.occurrences_of("self_signed") != 0, env_flag("SELF_SIGNED"), config.self_signed),
+                cert_cache: layered(matches.value_of_lossy("cert_cache").map(|path| path.to_string()), env_var("CERT_CACHE"), config.cert_cache),
+                index_files: layered(
+                    match (matches.occurrences_of("index"), matches.value_of_lossy("index")) {
+                        (0, _) => None,
+                        (_, Some(index_files)) => Some(split_list(&index_files)),
+                        (_, None) => Some(server_core::DEFAULT_INDEX_FILES.iter().map(|index_file| index_file.to_string()).collect()),
+                    },
+                    env_var("INDEX").map(|index_files| split_list(&index_files)),
+                    config.index),
+                no_listing: layered_flag(matches.occurrences_of("no_listing") != 0, env_flag("NO_LISTING"), config.no_listing),
+                listing_template: layered(matches.value_of_lossy("listing_template").map(|path| path.to_string()), env_var("LISTING_TEMPLATE"), config.listing_template),
+                spa: layered_flag(matches.occurrences_of("spa") != 0, env_flag("SPA"), config.spa),
+                spa_index: layered(matches.value_of_lossy("spa_index").map(|path| path.to_string()), env_var("SPA_INDEX"), config.spa_index),
+                spa_assets: layered(
+                    matches.value_of_lossy("spa_assets").map(|extensions| split_list(&extensions)),
+                    env_var("SPA_ASSETS").map(|extensions| split_list(&extensions)),
+                    config.spa_assets),
+                upload: layered_flag(matches.occurrences_of("upload") != 0, env_flag("UPLOAD"), config.upload),
+                upload_max_size: layered(matches.value_of_lossy("upload_max_size").map(|size| size.to_string()), env_var("UPLOAD_MAX_SIZE"), config.upload_max_size),
+                upload_max_request_size: layered(matches.value_of_lossy("upload_max_request_size").map(|size| size.to_string()), env_var("UPLOAD_MAX_REQUEST_SIZE"), config.upload_max_request_size),
+                upload_overwrite: layered(matches.value_of_lossy("upload_overwrite").map(|overwrite| overwrite.to_string()), env_var("UPLOAD_OVERWRITE"), config.upload_overwrite),
+                webdav: layered_flag(matches.occurrences_of("webdav") != 0, env_flag("WEBDAV"), config.webdav),
+                webdav_write: layered_flag(matches.occurrences_of("webdav_write") != 0, env_flag("WEBDAV_WRITE"), config.webdav_write),
+                auth: layered(matches.values_of_lossy("auth"), env_var("AUTH").map(|users| split_list(&users)), config.auth),
+                htpasswd: layered(matches.value_of_lossy("htpasswd").map(|path| path.to_string()), env_var("HTPASSWD"), config.htpasswd),
+                allow: layered(matches.values_of_lossy("allow"), env_var("ALLOW").map(|ranges| split_list(&ranges)), config.allow),
+                deny: layered(matches.values_of_lossy("deny"), env_var("DENY").map(|ranges| split_list(&ranges)), config.deny),
+                lan_only: layered_flag(matches.occurrences_of("lan_only") != 0, env_flag("LAN_ONLY"), config.lan_only),
+                watch: layered_flag(matches.occurrences_of("watch") != 0, env_flag("WATCH"), config.watch),
+                access_log: layered(matches.value_of_lossy("access_log").map(|format| format.to_string()), env_var("ACCESS_LOG"), config.access_log),
+                access_log_file: layered(matches.value_of_lossy("access_log_file").map(|path| path.to_string()), env_var("ACCESS_LOG_FILE"), config.access_log_file),
+                access_log_max_size: layered(matches.value_of_lossy("access_log_max_size").map(|size| size.to_string()), env_var("ACCESS_LOG_MAX_SIZE"), config.access_log_max_size),
+                access_log_daily: layered_flag(matches.occurrences_of("access_log_daily") != 0, env_flag("ACCESS_LOG_DAILY"), config.access_log_daily),
+                access_log_compress: layered_flag(matches.occurrences_of("access_log_compress") != 0, env_flag("ACCESS_LOG_COMPRESS"), config.access_log_compress),
+                access_log_max_files: layered(matches.value_of_lossy("access_log_max_files").map(|count| count.to_string()), env_var("ACCESS_LOG_MAX_FILES"), config.access_log_max_files.map(|count| count.to_string())),
+                share: layered(matches.values_of_lossy("share"), env_var("SHARE").map(|files| split_list(&files)), config.share),
+                share_expires: layered(matches.value_of_lossy("share_expires").map(|duration| duration.to_string()), env_var("SHARE_EXPIRES"), config.share_expires),
+                share_max_downloads: layered(matches.value_of_lossy("share_max_downloads").map(|count| count.to_string()), env_var("SHARE_MAX_DOWNLOADS"), config.share_max_downloads.map(|count| count.to_string())),
+                share_secret: layered(matches.value_of_lossy("share_secret").map(|secret| secret.to_string()), env_var("SHARE_SECRET"), config.share_secret),
+                proxy: layered(matches.values_of_lossy("proxy"), env_var("PROXY").map(|rules| split_list(&rules)), config.proxy),
+                compress: layered_flag(matches.occurrences_of("compress") != 0, env_flag("COMPRESS"), config.compress),
+                compress_include: layered(matches.values_of_lossy("compress_include"), env_var("COMPRESS_INCLUDE").map(|content_types| split_list(&content_types)), config.compress_include),
+                compress_exclude: layered(matches.values_of_lossy("compress_exclude"), env_var("COMPRESS_EXCLUDE").map(|content_types| split_list(&content_types)), config.compress_exclude),
+                compress_min_size: layered(matches.value_of_lossy("compress_min_size").map(|size| size.to_string()), env_var("COMPRESS_MIN_SIZE"), config.compress_min_size),
+                precompressed: layered_flag(matches.occurrences_of("precompressed") != 0, env_flag("PRECOMPRESSED"), config.precompressed),
+                config_file,
             }
         )
     }
 }
-
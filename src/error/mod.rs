@@ -0,0 +1,158 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use super::server_core::ListenAddr;
+
+/// # Errors that prevent happy server from delivering
+/// The viewer explains each variant to the user in their language,
+/// the `Display` implementation is a short English description for logs.
+#[derive(Debug)]
+pub enum HappyServerError {
+    /// The port number is not a number between 0 and 65535, with the port used when none is given.
+    InvalidPort(String, u16),
+    /// The current directory could not be located.
+    CurrentDir(io::Error),
+    /// The distribution directory does not exist or cannot be accessed.
+    DistributionDirNotFound(PathBuf, io::Error),
+    /// The distribution path exists but is not a directory.
+    NotADirectory(PathBuf),
+    /// The uri prefix starts with "/" or contains "//".
+    InvalidPrefix(String),
+    /// The bind address is neither an IP address nor a resolvable host name.
+    InvalidBindAddress(String, io::Error),
+    /// The listen address is not in a known form.
+    InvalidListen(String),
+    /// Only the certificate was specified.
+    MissingKey,
+    /// Only the private key was specified.
+    MissingCrt,
+    /// The certificate file could not be opened or read.
+    CrtFile(PathBuf, io::Error),
+    /// The private key file could not be opened or read.
+    KeyFile(PathBuf, io::Error),
+    /// A self-signed certificate was requested together with certificate files.
+    SelfSignedWithFiles,
+    /// The self-signed certificate could not be generated.
+    SelfSigned(rcgen::RcgenError),
+    /// The generated certificate could not be saved to the cache directory.
+    CertCache(PathBuf, io::Error),
+    /// The certificate cache directory is inside the distribution directory.
+    CertCacheInDistDir(PathBuf),
+    /// The certificate is not a PEM encoded certificate.
+    CertParse,
+    /// The private key is not a PEM encoded key.
+    KeyParse,
+    /// The private key file did not contain any PKCS#8 or RSA key.
+    EmptyKeyList,
+    /// rustls did not accept the certificate and private key pair.
+    InvalidCertificate(rustls::TLSError),
+    /// A listener delivers via https, but no certificate was given.
+    MissingCertificate(ListenAddr),
+    /// This build of happy server cannot deliver via https.
+    SslDisabled,
+    /// Not allowed to listen on the address, e.g. a privileged port without root.
+    PermissionDenied(ListenAddr),
+    /// The address could not be bound, e.g. because the port is already in use.
+    Bind(ListenAddr, io::Error),
+    /// The template file of the listing could not be read.
+    ListingTemplate(PathBuf, io::Error),
+    /// The index file of the single-page application does not exist.
+    SpaIndexNotFound(PathBuf),
+    /// The config file could not be opened or read.
+    ConfigFile(PathBuf, io::Error),
+    /// The config file is not valid TOML or contains unknown keys.
+    ConfigParse(PathBuf, toml::de::Error),
+    /// The size is not a number of bytes such as "100M".
+    InvalidSize(String),
+    /// The overwrite policy is not "reject", "rename" or "replace".
+    InvalidOverwrite(String),
+    /// A user for authentication is not given as "user:password".
+    InvalidAuth(String),
+    /// The user name of the numbered `--auth` entry is empty.
+    MissingAuthUser(usize),
+    /// The password of the user is hashed with an unsupported algorithm.
+    UnsupportedHash(String),
+    /// The htpasswd file could not be read.
+    HtpasswdFile(PathBuf, io::Error),
+    /// The line of the htpasswd file is invalid.
+    HtpasswdLine(PathBuf, usize),
+    /// The shared file is not a file below the distribution directory.
+    ShareFile(String),
+    /// The duration is not a number of seconds such as "30m".
+    InvalidDuration(String),
+    /// The download limit is not a positive number.
+    InvalidMaxDownloads(String),
+    /// The address range is not in CIDR notation such as "192.168.0.0/16".
+    InvalidIpRange(String),
+    /// The content type to compress is not "type/subtype", where the subtype may be "*".
+    InvalidContentType(String),
+    /// The proxy rule is not "path=url" with an http or https URL.
+    InvalidProxy(String),
+    /// The log format is not "common", "combined" or "json".
+    InvalidLogFormat(String),
+    /// The number of rotated log files is not a number.
+    InvalidMaxFiles(String),
+    /// The access log file could not be opened.
+    AccessLogFile(PathBuf, io::Error),
+    /// The distribution directory could not be watched for live reload.
+    Watch(String),
+    /// No random secret could be generated for the share links.
+    RandomSecret,
+}
+
+impl fmt::Display for HappyServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidPort(port, _) => write!(f, "invalid port number: {}", port),
+            Self::CurrentDir(e) => write!(f, "could not locate the current directory: {}", e),
+            Self::DistributionDirNotFound(path, e) => write!(f, "could not access the distribution directory {}: {}", path.display(), e),
+            Self::NotADirectory(path) => write!(f, "{} is not a directory", path.display()),
+            Self::InvalidPrefix(prefix) => write!(f, "invalid uri prefix: {}", prefix),
+            Self::InvalidBindAddress(host, e) => write!(f, "invalid bind address {}: {}", host, e),
+            Self::InvalidListen(listen) => write!(f, "invalid listen address: {}", listen),
+            Self::MissingKey => write!(f, "a certificate was given without a private key"),
+            Self::MissingCrt => write!(f, "a private key was given without a certificate"),
+            Self::CrtFile(path, e) => write!(f, "could not read the certificate file {}: {}", path.display(), e),
+            Self::KeyFile(path, e) => write!(f, "could not read the private key file {}: {}", path.display(), e),
+            Self::SelfSignedWithFiles => write!(f, "a self-signed certificate cannot be used with certificate files"),
+            Self::SelfSigned(e) => write!(f, "could not generate a self-signed certificate: {}", e),
+            Self::CertCache(path, e) => write!(f, "could not save the certificate to {}: {}", path.display(), e),
+            Self::CertCacheInDistDir(path) => write!(f, "the certificate cache {} is inside the distribution directory", path.display()),
+            Self::CertParse => write!(f, "could not parse the certificate"),
+            Self::KeyParse => write!(f, "could not parse the private key"),
+            Self::EmptyKeyList => write!(f, "no private key was found"),
+            Self::InvalidCertificate(e) => write!(f, "invalid certificate or private key: {}", e),
+            Self::MissingCertificate(addr) => write!(f, "no certificate for the https listener on {}", addr),
+            Self::SslDisabled => write!(f, "https is disabled in this build"),
+            Self::PermissionDenied(addr) => write!(f, "permission denied to listen on {}", addr),
+            Self::Bind(addr, e) => write!(f, "could not listen on {}: {}", addr, e),
+            Self::ListingTemplate(path, e) => write!(f, "could not read the listing template {}: {}", path.display(), e),
+            Self::SpaIndexNotFound(path) => write!(f, "the index file {} of the single-page application was not found", path.display()),
+            Self::ConfigFile(path, e) => write!(f, "could not read the config file {}: {}", path.display(), e),
+            Self::ConfigParse(path, e) => write!(f, "invalid config file {}: {}", path.display(), e),
+            Self::InvalidSize(size) => write!(f, "invalid size: {}", size),
+            Self::InvalidOverwrite(overwrite) => write!(f, "invalid overwrite policy: {}", overwrite),
+            Self::InvalidAuth(user) => write!(f, "expected user:password, got {}", user),
+            Self::MissingAuthUser(number) => write!(f, "the user name of authentication entry {} is empty", number),
+            Self::UnsupportedHash(user) => write!(f, "unsupported password hash of the user {}", user),
+            Self::HtpasswdFile(path, e) => write!(f, "could not read the htpasswd file {}: {}", path.display(), e),
+            Self::HtpasswdLine(path, line) => write!(f, "invalid line {} in the htpasswd file {}", line, path.display()),
+            Self::ShareFile(path) => write!(f, "cannot share {}: not a file below the distribution directory", path),
+            Self::InvalidDuration(duration) => write!(f, "invalid duration: {}", duration),
+            Self::InvalidMaxDownloads(max_downloads) => write!(f, "invalid download limit: {}", max_downloads),
+            Self::InvalidIpRange(range) => write!(f, "invalid address range: {}", range),
+            Self::InvalidContentType(content_type) => write!(f, "invalid content type: {}", content_type),
+            Self::InvalidProxy(rule) => write!(f, "invalid proxy rule: {}", rule),
+            Self::InvalidLogFormat(format) => write!(f, "invalid log format: {}", format),
+            Self::InvalidMaxFiles(max_files) => write!(f, "invalid number of log files: {}", max_files),
+            Self::AccessLogFile(path, e) => write!(f, "could not open the access log {}: {}", path.display(), e),
+            Self::Watch(e) => write!(f, "could not watch the distribution directory: {}", e),
+            Self::RandomSecret => write!(f, "could not generate a secret for the share links"),
+        }
+    }
+}
+
+impl std::error::Error for HappyServerError {}
@@ -0,0 +1,40 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+//! # Happy Server
+//! A simple web server that delivers a directory.
+//!
+//! The `happy_server` binary is built on this library,
+//! so the same server can be embedded in test harnesses and tools.
+//!
+//! ```no_run
+//! use happy_server::HappyServerBuilder;
+//!
+//! #[actix_web::main]
+//! async fn main() {
+//!     let server = HappyServerBuilder::new("./dist")
+//!         .port(8080)
+//!         .prefix("app")
+//!         .start().await
+//!         .expect("could not start happy server");
+//!     // http://localhost:8080/app/ is now available
+//!     server.stop().await;
+//! }
+//! ```
+
+pub mod error;
+pub mod server_core;
+pub mod viewer;
+pub mod model;
+
+pub use error::HappyServerError;
+pub use model::{HappyServerModel, HappyServerModelViewer, HappyServerPreModel, ParameterSource};
+pub use server_core::{HappyServer, HappyServerBuilder, HappyServerViewer, Ssl};
+pub use viewer::{StreamViewer, StyledString};
+
+/// Language settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    Japanese,
+    English
+}
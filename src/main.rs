@@ -1,14 +1,8 @@
 // Copyright (c) 2021 Daichi Aoki  
 // Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
-mod server_core;
-mod viewer;
 mod controller;
-mod model;
 
-use model::HappyServerModelViewer;
-use viewer::*;
-use actix_web::dev::Server;
-use server_core::{HappyServer, HappyServerViewer};
+use happy_server::{HappyServer, HappyServerModelViewer, HappyServerViewer, Language, StreamViewer, StyledString};
 
 // Compile-time defaults
 #[cfg(any(feature = "japanese",not(feature = "english")))]
@@ -16,13 +10,6 @@ const COMPILE_TIME_DEFAULT_LANGUAGE:Language = Language::Japanese;
 #[cfg(feature = "english")]
 const COMPILE_TIME_DEFAULT_LANGUAGE:Language = Language::English;
 
-/// Language settings
-#[allow(dead_code)]
-pub enum Language {
-    Japanese,
-    English
-}
-
 /// # application entry point
 /// Note: Although it is an async function, it is converted to a normal function signature by the #[actix_web::main] attribute.
 #[actix_web::main]
@@ -34,21 +21,27 @@ async fn main() {
     // setup cli arguments getter
     let cli_arg_getter = controller::CliArgGetter{language};
     // get cli arguments
-    let (language, color, using_clipboard, happy_server_model) = cli_arg_getter.get_arguments();
+    let (language, color, using_clipboard, clipboard_url, qr_code, happy_server_model) = cli_arg_getter.get_arguments();
     // setup cli style strings
     let style = if color {StyledString::colored()} else {StyledString::no_colored()};
     // setup app viewer
-    let mut viewer = StreamViewer{language, style, writer: std::io::stdout(), using_clipboard};
+    let mut viewer = StreamViewer{language, style, writer: std::io::stdout(), using_clipboard, clipboard_url, qr_code};
     // convert model to server builder, then output with viewer
-    let server_pre_model = happy_server_model.to_server_pre_medel();
+    let server_pre_model = happy_server_model.into_server_pre_model();
     viewer.output_server_pre_model(&server_pre_model).unwrap_or_else(|_op|{
         // if the output of the viewer is not successful
         std::process::exit(1)
     });
-    let mut server_builder = server_pre_model.to_server_builder().unwrap_or_else(|_op|{
+    // the pages of the server are in the same language as the output
+    let mut server_builder = server_pre_model.into_server_builder(language).unwrap_or_else(|_op|{
         // if there is error cli argument
         std::process::exit(0)
     });
+    // events such as uploads happen on the worker threads, they are printed here
+    let (event_sender, mut event_receiver) = tokio::sync::mpsc::unbounded_channel();
+    server_builder.on_event = Some(std::sync::Arc::new(move |event| {
+        let _ = event_sender.send(event);
+    }));
 
     // run happy server and output server start result
     let start_server = server_builder.start_server().await;
@@ -63,10 +56,22 @@ async fn main() {
 
     let server = HappyServer{server, hs_builder: server_builder};
 
-    // wait finish signal
+    // output events until the finish signal
     let rt = tokio::runtime::Runtime::new().unwrap();
     rt.block_on(async {
-        tokio::signal::ctrl_c().await.unwrap();
+        let ctrl_c = tokio::signal::ctrl_c();
+        tokio::pin!(ctrl_c);
+        loop {
+            tokio::select! {
+                result = &mut ctrl_c => {
+                    result.unwrap();
+                    break;
+                },
+                Some(event) = event_receiver.recv() => {
+                    let _ = viewer.output_event(&event);
+                },
+            }
+        }
     });
     
     // stop server and output stop server result
@@ -1,94 +1,772 @@
 // Copyright (c) 2021 Daichi Aoki  
 // Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
 
-use super::server_core::HappyServerBuilder;
+mod self_signed;
+
+use super::server_core::{AccessLog, Auth, Compression, HappyServerBuilder, IpFilter, IpRange, LogFormat, Password, Proxy, Rotation, ListenAddr, Listener, Overwrite, Share, ShareLink, Spa, Ssl, Upload, WebDav, DEFAULT_COMPRESS_MIN_SIZE, DEFAULT_SHARE_EXPIRES, DEFAULT_UPLOAD_MAX_REQUEST_SIZE, DEFAULT_UPLOAD_MAX_SIZE};
+use super::error::HappyServerError;
+use super::Language;
+use ring::rand::{SecureRandom, SystemRandom};
 use std::io;
-use std::net::Ipv4Addr;
-use std::num::ParseIntError;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
 
-const DEFAULT_IPV4_ADDR: Ipv4Addr  = Ipv4Addr::new(0, 0, 0, 0);
-const DEFAULT_HTTP_PORT: u16  = 80;
+pub const DEFAULT_IPV4_ADDR: Ipv4Addr  = Ipv4Addr::new(0, 0, 0, 0);
+pub const DEFAULT_HTTP_PORT: u16  = 80;
+pub const DEFAULT_HTTPS_PORT: u16  = 443;
+/// Prefix of the environment variables read by happy server, e.g. `HAPPY_SERVER_PORT`.
+pub const ENV_PREFIX: &str = "HAPPY_SERVER_";
 
+/// # Where a parameter came from
+/// When the same parameter is given in several places,
+/// the command line argument wins over the environment variable, which wins over the config file.
 pub enum ParameterSource<T> {
     Default(T),
-    CliArg(T)
+    CliArg(T),
+    /// `happy_server.toml` or the file given with `--config`.
+    ConfigFile(T),
+    /// `HAPPY_SERVER_*` environment variables.
+    Env(T),
 }
 impl<T> ParameterSource<T> {
     fn get_contents(self) -> T {
         match self {
             Self::Default(contents) => contents,
             Self::CliArg(contents) => contents,
+            Self::ConfigFile(contents) => contents,
+            Self::Env(contents) => contents,
+        }
+    }
+    pub fn get_ref(&self) -> &T {
+        match self {
+            Self::Default(contents) => contents,
+            Self::CliArg(contents) => contents,
+            Self::ConfigFile(contents) => contents,
+            Self::Env(contents) => contents,
+        }
+    }
+    /// # Borrow the contents, keeping the source
+    pub fn as_ref(&self) -> ParameterSource<&T> {
+        match self {
+            Self::Default(contents) => ParameterSource::Default(contents),
+            Self::CliArg(contents) => ParameterSource::CliArg(contents),
+            Self::ConfigFile(contents) => ParameterSource::ConfigFile(contents),
+            Self::Env(contents) => ParameterSource::Env(contents),
+        }
+    }
+    /// # Convert the contents, keeping the source
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> ParameterSource<U> {
+        match self {
+            Self::Default(contents) => ParameterSource::Default(f(contents)),
+            Self::CliArg(contents) => ParameterSource::CliArg(f(contents)),
+            Self::ConfigFile(contents) => ParameterSource::ConfigFile(f(contents)),
+            Self::Env(contents) => ParameterSource::Env(f(contents)),
         }
     }
 }
 
+/// # Parameters as entered by the user
+/// Convert with `into_server_pre_model` to validate them.
 pub struct HappyServerModel {
-    pub port: Option<String>,
-    pub distribution_dir: Option<String>,
-    pub uri_prefix: Option<String>
+    pub bind: Option<ParameterSource<String>>,
+    pub port: Option<ParameterSource<String>>,
+    pub distribution_dir: Option<ParameterSource<String>>,
+    pub uri_prefix: Option<ParameterSource<String>>,
+    pub listen: Option<ParameterSource<Vec<String>>>,
+    pub auto_port: ParameterSource<bool>,
+    pub server_crt: Option<ParameterSource<String>>,
+    pub server_key: Option<ParameterSource<String>>,
+    pub self_signed: ParameterSource<bool>,
+    pub cert_cache: Option<ParameterSource<String>>,
+    /// File names tried when a directory is requested.
+    pub index_files: Option<ParameterSource<Vec<String>>>,
+    pub no_listing: ParameterSource<bool>,
+    /// HTML template file of the listing.
+    pub listing_template: Option<ParameterSource<String>>,
+    pub spa: ParameterSource<bool>,
+    /// Index file of the single-page application, relative to the distribution directory.
+    pub spa_index: Option<ParameterSource<String>>,
+    /// Extensions of the paths that return 404 instead of the index file, in SPA mode.
+    pub spa_assets: Option<ParameterSource<Vec<String>>>,
+    pub upload: ParameterSource<bool>,
+    /// Largest uploaded file, e.g. "100M".
+    pub upload_max_size: Option<ParameterSource<String>>,
+    /// Largest form post, e.g. "500M".
+    pub upload_max_request_size: Option<ParameterSource<String>>,
+    /// "reject", "rename" or "replace".
+    pub upload_overwrite: Option<ParameterSource<String>>,
+    pub webdav: ParameterSource<bool>,
+    /// Allow WebDAV clients to change files.
+    pub webdav_write: ParameterSource<bool>,
+    /// Users as "user:password", the password may be a bcrypt or argon2 hash.
+    pub auth: Option<ParameterSource<Vec<String>>>,
+    pub htpasswd: Option<ParameterSource<String>>,
+    /// Address ranges in CIDR notation.
+    pub allow: Option<ParameterSource<Vec<String>>>,
+    pub deny: Option<ParameterSource<Vec<String>>>,
+    /// Allow the local network, in addition to `allow`.
+    pub lan_only: ParameterSource<bool>,
+    /// Reload the browsers when files change.
+    pub watch: ParameterSource<bool>,
+    /// "common", "combined" or "json".
+    pub access_log: Option<ParameterSource<String>>,
+    /// Without it, the log is printed.
+    pub access_log_file: Option<ParameterSource<String>>,
+    /// Rotate the log file before it grows larger, e.g. "100M".
+    pub access_log_max_size: Option<ParameterSource<String>>,
+    pub access_log_daily: ParameterSource<bool>,
+    /// Compress the rotated files with gzip.
+    pub access_log_compress: ParameterSource<bool>,
+    /// Number of rotated files kept.
+    pub access_log_max_files: Option<ParameterSource<String>>,
+    /// Files handed out with signed links, relative to the distribution directory.
+    pub share: Option<ParameterSource<Vec<String>>>,
+    /// How long the links are valid, e.g. "24h".
+    pub share_expires: Option<ParameterSource<String>>,
+    pub share_max_downloads: Option<ParameterSource<String>>,
+    /// Key of the signatures. Without one, a random key is used and links expire with the server.
+    pub share_secret: Option<ParameterSource<String>>,
+    /// Proxy rules as "path=url", e.g. "/api=http://127.0.0.1:3000".
+    pub proxy: Option<ParameterSource<Vec<String>>>,
+    /// Compress responses on the fly.
+    pub compress: ParameterSource<bool>,
+    /// Content types to compress, e.g. "text/*".
+    pub compress_include: Option<ParameterSource<Vec<String>>>,
+    pub compress_exclude: Option<ParameterSource<Vec<String>>>,
+    /// Smallest body compressed, e.g. "1K".
+    pub compress_min_size: Option<ParameterSource<String>>,
+    /// Deliver precompressed siblings such as "main.js.br".
+    pub precompressed: ParameterSource<bool>,
+    /// The config file that was read, or why it could not be read.
+    /// None if no config file was given or found.
+    pub config_file: Option<Result<PathBuf, HappyServerError>>,
 }
 
-use std::path::PathBuf;
-use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use std::fs::File;
 
+/// # Output the result of validating the parameters
 pub trait HappyServerModelViewer {
     fn output_server_pre_model(&mut self, model: & HappyServerPreModel) -> io::Result<()>;
 }
 
+/// # Validated parameters
+/// Each field holds either the value or the reason it is invalid.
 pub struct HappyServerPreModel {
-    pub port: Result<u16, ParseIntError>,
-    pub distribution_dir: ParameterSource<Result<PathBuf, ()>>,
-    pub uri_prefix: ParameterSource<Result<String, ()>>
+    pub bind: ParameterSource<Result<IpAddr, HappyServerError>>,
+    pub port: ParameterSource<Result<u16, HappyServerError>>,
+    pub distribution_dir: ParameterSource<Result<PathBuf, HappyServerError>>,
+    pub uri_prefix: ParameterSource<Result<String, HappyServerError>>,
+    /// Listeners given in addition to (or instead of) the one made from `bind` and `port`.
+    pub listeners: ParameterSource<Result<Vec<Listener>, HappyServerError>>,
+    pub auto_port: bool,
+    pub ssl: ParameterSource<Result<Option<Ssl>, HappyServerError>>,
+    pub index_files: Vec<String>,
+    pub listing: bool,
+    /// Contents of the listing template file.
+    pub listing_template: ParameterSource<Result<Option<String>, HappyServerError>>,
+    /// Single-page application mode, the source is the one of the index file.
+    pub spa: ParameterSource<Result<Option<Spa>, HappyServerError>>,
+    pub upload: bool,
+    pub upload_max_size: ParameterSource<Result<u64, HappyServerError>>,
+    pub upload_max_request_size: ParameterSource<Result<u64, HappyServerError>>,
+    pub upload_overwrite: ParameterSource<Result<Overwrite, HappyServerError>>,
+    pub webdav: Option<WebDav>,
+    /// Users given with `--auth`.
+    pub auth: ParameterSource<Result<Vec<(String, Password)>, HappyServerError>>,
+    /// Users of the htpasswd file.
+    pub htpasswd: ParameterSource<Result<Vec<(String, Password)>, HappyServerError>>,
+    pub allow: ParameterSource<Result<Vec<IpRange>, HappyServerError>>,
+    pub deny: ParameterSource<Result<Vec<IpRange>, HappyServerError>>,
+    pub lan_only: bool,
+    pub watch: bool,
+    pub access_log: ParameterSource<Result<Option<LogFormat>, HappyServerError>>,
+    pub access_log_file: Option<PathBuf>,
+    pub access_log_max_size: ParameterSource<Result<Option<u64>, HappyServerError>>,
+    pub access_log_daily: bool,
+    pub access_log_compress: bool,
+    pub access_log_max_files: ParameterSource<Result<Option<usize>, HappyServerError>>,
+    /// Shared files, with "/" separators.
+    pub share: ParameterSource<Result<Vec<String>, HappyServerError>>,
+    /// Seconds until the links expire.
+    pub share_expires: ParameterSource<Result<u64, HappyServerError>>,
+    pub share_max_downloads: ParameterSource<Result<Option<u32>, HappyServerError>>,
+    /// The given secret, or a random one if files are shared without it.
+    pub share_secret: ParameterSource<Result<Option<Vec<u8>>, HappyServerError>>,
+    pub proxies: ParameterSource<Result<Vec<Proxy>, HappyServerError>>,
+    pub compress: bool,
+    pub compress_include: ParameterSource<Result<Vec<String>, HappyServerError>>,
+    pub compress_exclude: ParameterSource<Result<Vec<String>, HappyServerError>>,
+    pub compress_min_size: ParameterSource<Result<u64, HappyServerError>>,
+    pub precompressed: bool,
+    pub config_file: Option<Result<PathBuf, HappyServerError>>,
+}
+
+/// # Check that the distribution directory can be delivered
+fn check_distribution_dir(path: PathBuf) -> Result<PathBuf, HappyServerError> {
+    match std::fs::metadata(&path) {
+        Ok(metadata) if metadata.is_dir() => Ok(path),
+        Ok(_) => Err(HappyServerError::NotADirectory(path)),
+        Err(e) => Err(HappyServerError::DistributionDirNotFound(path, e)),
+    }
+}
+
+/// # Resolve the address to listen on
+/// Accepts IPv4 and IPv6 addresses (optionally in brackets) and host names.
+/// A host name is resolved to its first address.
+fn resolve_bind_address(host: String) -> Result<IpAddr, HappyServerError> {
+    let ip = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = ip.parse::<IpAddr>() {
+        return Ok(ip);
+    }
+    match (host.as_str(), 0).to_socket_addrs() {
+        Ok(mut addrs) => match addrs.next() {
+            Some(addr) => Ok(addr.ip()),
+            None => Err(HappyServerError::InvalidBindAddress(host, io::Error::new(io::ErrorKind::NotFound, "no address found"))),
+        },
+        Err(e) => Err(HappyServerError::InvalidBindAddress(host, e)),
+    }
+}
+
+/// # Parse a listen address
+/// Accepted forms are a port ("8080", ":8080"), an address ("127.0.0.1:8080", "[::]:8080", "localhost:8080"),
+/// any of them after "http://" or "https://", and "unix:/path/to/socket".
+fn parse_listen(listen: String) -> Result<Listener, HappyServerError> {
+    #[cfg(unix)]
+    if let Some(path) = listen.strip_prefix("unix:") {
+        return match path {
+            "" => Err(HappyServerError::InvalidListen(listen)),
+            path => Ok(Listener::unix(path)),
+        };
+    }
+    let (tls, addr) = match (listen.strip_prefix("https://"), listen.strip_prefix("http://")) {
+        (Some(addr), _) => (true, addr),
+        (_, Some(addr)) => (false, addr),
+        (None, None) => (false, listen.as_str()),
+    };
+    let addr = addr.trim_end_matches('/');
+    let default_port = if tls {DEFAULT_HTTPS_PORT} else {DEFAULT_HTTP_PORT};
+    let socket_addr = if let Ok(port) = addr.trim_start_matches(':').parse::<u16>() {
+        SocketAddr::new(IpAddr::V4(DEFAULT_IPV4_ADDR), port)
+    } else if let Ok(socket_addr) = addr.parse::<SocketAddr>() {
+        socket_addr
+    } else if let Ok(ip) = addr.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        SocketAddr::new(ip, default_port)
+    } else {
+        // host name, with or without a port
+        let resolved = match addr.to_socket_addrs() {
+            Ok(addrs) => Ok(addrs),
+            Err(_) => (addr, default_port).to_socket_addrs(),
+        };
+        match resolved.ok().and_then(|mut addrs| addrs.next()) {
+            Some(socket_addr) => socket_addr,
+            None => return Err(HappyServerError::InvalidListen(listen)),
+        }
+    };
+    Ok(Listener{addr: ListenAddr::Tcp(socket_addr), tls})
+}
+
+/// # Check the uri prefix
+/// It cannot start with "/" and cannot contain "//".
+pub fn check_uri_prefix(uri_prefix: String) -> Result<String, HappyServerError> {
+    if uri_prefix.starts_with('/') || uri_prefix.contains("//") {
+        Err(HappyServerError::InvalidPrefix(uri_prefix))
+    } else {
+        Ok(uri_prefix)
+    }
+}
+
+/// # Parse a size in bytes
+/// A number optionally followed by K, M, G or T (powers of 1024), and optionally "B" or "iB", e.g. "100M", "1GiB".
+fn parse_size(size: String) -> Result<u64, HappyServerError> {
+    let upper = size.trim().to_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (number, multiplier) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1u64 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('T') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1),
+    };
+    number.trim().parse::<u64>().ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or(HappyServerError::InvalidSize(size))
+}
+
+/// # Parse the overwrite policy of uploads
+fn parse_overwrite(overwrite: String) -> Result<Overwrite, HappyServerError> {
+    match overwrite.to_lowercase().as_str() {
+        "reject" => Ok(Overwrite::Reject),
+        "rename" => Ok(Overwrite::Rename),
+        "replace" => Ok(Overwrite::Replace),
+        _ => Err(HappyServerError::InvalidOverwrite(overwrite)),
+    }
+}
+
+/// # Parse users given as "user:password"
+fn parse_auth(users: Vec<String>) -> Result<Vec<(String, Password)>, HappyServerError> {
+    users.into_iter().enumerate().map(|(number, user)| match user.split_once(':') {
+        Some((name, password)) if !name.is_empty() => match Password::parse(password) {
+            Some(password) => Ok((name.to_string(), password)),
+            None => Err(HappyServerError::UnsupportedHash(name.to_string())),
+        },
+        // the password is not shown, the entry is named by its position
+        Some(_) => Err(HappyServerError::MissingAuthUser(number + 1)),
+        None => Err(HappyServerError::InvalidAuth(user)),
+    }).collect()
+}
+
+/// # Read the users of an htpasswd file
+fn read_htpasswd(path: String) -> Result<Vec<(String, Password)>, HappyServerError> {
+    let path = PathBuf::from(path);
+    let contents = std::fs::read_to_string(&path).map_err(|e| HappyServerError::HtpasswdFile(path.clone(), e))?;
+    Auth::parse_htpasswd(&contents).map_err(|line| HappyServerError::HtpasswdLine(path, line))
+}
+
+fn parse_log_format(format: String) -> Result<Option<LogFormat>, HappyServerError> {
+    match format.to_lowercase().as_str() {
+        "common" => Ok(Some(LogFormat::Common)),
+        "combined" => Ok(Some(LogFormat::Combined)),
+        "json" => Ok(Some(LogFormat::Json)),
+        _ => Err(HappyServerError::InvalidLogFormat(format)),
+    }
+}
+
+fn parse_max_files(max_files: String) -> Result<Option<usize>, HappyServerError> {
+    max_files.trim().parse::<usize>().map(Some).map_err(|_| HappyServerError::InvalidMaxFiles(max_files))
+}
+
+fn parse_ip_ranges(ranges: Vec<String>) -> Result<Vec<IpRange>, HappyServerError> {
+    ranges.into_iter().map(|range| IpRange::parse(&range).ok_or(HappyServerError::InvalidIpRange(range))).collect()
+}
+
+/// # Check content types such as "text/html" or "text/*"
+fn check_content_types(content_types: Vec<String>) -> Result<Vec<String>, HappyServerError> {
+    for content_type in &content_types {
+        match content_type.trim().split_once('/') {
+            Some((type_, subtype)) if !type_.is_empty() && !subtype.is_empty() && !subtype.contains('/') => (),
+            _ => return Err(HappyServerError::InvalidContentType(content_type.clone())),
+        }
+    }
+    Ok(content_types)
+}
+
+fn parse_proxies(rules: Vec<String>) -> Result<Vec<Proxy>, HappyServerError> {
+    rules.into_iter().map(|rule| Proxy::parse(&rule).ok_or(HappyServerError::InvalidProxy(rule))).collect()
+}
+
+/// # Parse a duration in seconds
+/// A number optionally followed by s, m, h or d, e.g. "90", "30m", "7d".
+fn parse_duration(duration: String) -> Result<u64, HappyServerError> {
+    let lower = duration.trim().to_lowercase();
+    let (number, multiplier) = match lower.chars().last() {
+        Some('s') => (&lower[..lower.len() - 1], 1u64),
+        Some('m') => (&lower[..lower.len() - 1], 60),
+        Some('h') => (&lower[..lower.len() - 1], 60 * 60),
+        Some('d') => (&lower[..lower.len() - 1], 24 * 60 * 60),
+        _ => (lower.as_str(), 1),
+    };
+    number.trim().parse::<u64>().ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or(HappyServerError::InvalidDuration(duration))
+}
+
+fn parse_max_downloads(max_downloads: String) -> Result<Option<u32>, HappyServerError> {
+    match max_downloads.trim().parse::<u32>() {
+        Ok(max_downloads) if max_downloads > 0 => Ok(Some(max_downloads)),
+        _ => Err(HappyServerError::InvalidMaxDownloads(max_downloads)),
+    }
+}
+
+/// # Check that the shared files exist below the distribution directory
+/// Hidden files and paths leaving the directory cannot be shared, they are never delivered.
+/// Not checked if the distribution directory itself is invalid, that error is reported instead.
+fn check_share(files: Vec<String>, distribution_dir: Option<&PathBuf>) -> Result<Vec<String>, HappyServerError> {
+    files.into_iter().map(|file| {
+        let segments: Vec<&str> = file.split(['/', '\\']).filter(|segment| !segment.is_empty()).collect();
+        let valid = !segments.is_empty() && segments.iter().all(|segment| !segment.starts_with('.') && !segment.contains(':'));
+        match distribution_dir {
+            _ if !valid => Err(HappyServerError::ShareFile(file)),
+            Some(distribution_dir) if !segments.iter().fold(distribution_dir.clone(), |path, segment| path.join(segment)).is_file() => Err(HappyServerError::ShareFile(file)),
+            _ => Ok(segments.join("/")),
+        }
+    }).collect()
+}
+
+/// # Generate a secret for the share links of this run
+fn random_secret() -> Result<Vec<u8>, HappyServerError> {
+    let mut secret = vec![0u8; 32];
+    SecureRandom::fill(&SystemRandom::new(), &mut secret).map_err(|_| HappyServerError::RandomSecret)?;
+    Ok(secret)
+}
+
+/// # Sign the shared files
+/// Share-link mode is on if a secret is given or was generated for the shared files, so that links of earlier runs stay valid.
+fn share(files: Vec<String>, expires: u64, max_downloads: Option<u32>, secret: Option<Vec<u8>>) -> Result<Option<Share>, HappyServerError> {
+    let secret = match secret {
+        Some(secret) => secret,
+        None => return Ok(None),
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default();
+    let links = files.into_iter()
+        .map(|path| ShareLink{path, expires: now.saturating_add(expires), max_downloads})
+        .collect();
+    Ok(Some(Share{secret, links}))
+}
+
+/// # Check that the index file of the single-page application exists
+/// Not checked if the distribution directory itself is invalid, that error is reported instead.
+fn check_spa(spa: Spa, distribution_dir: Option<&PathBuf>) -> Result<Option<Spa>, HappyServerError> {
+    match distribution_dir.map(|distribution_dir| distribution_dir.join(&spa.index_file)) {
+        Some(index_file) if !index_file.is_file() => Err(HappyServerError::SpaIndexNotFound(index_file)),
+        _ => Ok(Some(spa)),
+    }
+}
+
+/// # Open the certificate and private key files
+fn open_ssl_files(server_crt: String, server_key: String) -> Result<Ssl, HappyServerError> {
+    let server_crt = PathBuf::from(server_crt);
+    let server_key = PathBuf::from(server_key);
+    let crt_file = File::open(&server_crt).map_err(|e| HappyServerError::CrtFile(server_crt, e))?;
+    let key_file = File::open(&server_key).map_err(|e| HappyServerError::KeyFile(server_key, e))?;
+    Ok(Ssl {
+        server_crt: Box::new(io::BufReader::new(crt_file)),
+        server_key: Box::new(io::BufReader::new(key_file)),
+        fingerprint: None,
+    })
+}
+
+/// # Whether a path is inside a directory
+/// The path does not have to exist yet; its nearest existing ancestor is resolved instead.
+fn is_inside(path: &Path, dir: &Path) -> bool {
+    let dir = match dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(_) => return false,
+    };
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(resolved) = existing.canonicalize() {
+            return rest.iter().rev().fold(resolved, |resolved, name| resolved.join(name)).starts_with(&dir);
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = if parent.as_os_str().is_empty() {Path::new(".")} else {parent};
+            },
+            _ => return false,
+        }
+    }
+}
+
+/// # Generate a self-signed certificate in memory
+/// The cache directory must not be delivered, because it holds the private key.
+fn self_signed_ssl(cert_cache: Option<String>, distribution_dir: Option<&PathBuf>) -> Result<Ssl, HappyServerError> {
+    let cert_cache = cert_cache.map(PathBuf::from);
+    if let (Some(cert_cache), Some(distribution_dir)) = (&cert_cache, distribution_dir) {
+        if is_inside(cert_cache, distribution_dir) {
+            return Err(HappyServerError::CertCacheInDistDir(cert_cache.clone()));
+        }
+    }
+    let self_signed = self_signed::load_or_generate(cert_cache.as_deref())?;
+    Ok(Ssl {
+        server_crt: Box::new(io::Cursor::new(self_signed.server_crt.into_bytes())),
+        server_key: Box::new(io::Cursor::new(self_signed.server_key.into_bytes())),
+        fingerprint: Some(self_signed.fingerprint),
+    })
 }
 
 impl HappyServerModel {
     /// # Form incomplete parameters.
     /// Process the incomplete set of server parameters by putting in default values, etc., so that the server can be started.
     /// If the values are not enough, use the viewer in the argument to display errors, etc.
-    pub fn to_server_pre_medel(self) -> HappyServerPreModel  {
-        HappyServerPreModel {
-            port: match self.port {
-                Some(port) => port.parse::<u16>(),
-                None => Ok(u16::into(DEFAULT_HTTP_PORT))
+    pub fn into_server_pre_model(self) -> HappyServerPreModel  {
+        let distribution_dir = match self.distribution_dir {
+            Some(path) => path.map(|path| check_distribution_dir(PathBuf::from(path))),
+            None => match std::env::current_dir() {
+                Ok(path) => ParameterSource::Default(check_distribution_dir(path)),
+                Err(e) => ParameterSource::Default(Err(HappyServerError::CurrentDir(e)))
+            }
+        };
+        let self_signed = *self.self_signed.get_ref();
+        let ssl = match (self.server_crt, self.server_key) {
+            (Some(source), _) | (_, Some(source)) if self_signed => source.map(|_| Err(HappyServerError::SelfSignedWithFiles)),
+            // Errors about the cache are reported on the parameter that named it.
+            (None, None) if self_signed => match self.cert_cache {
+                Some(cert_cache) => cert_cache.map(|cert_cache| self_signed_ssl(Some(cert_cache), distribution_dir.get_ref().as_ref().ok()).map(Some)),
+                None => self.self_signed.map(|_| self_signed_ssl(None, None).map(Some)),
             },
-            distribution_dir:  match self.distribution_dir {
-                Some(path) => match PathBuf::from_str(&path){
-                    Ok(path) => ParameterSource::CliArg(Ok(path)),
-                    Err(_) => ParameterSource::CliArg(Err(()))
+            (Some(server_crt), Some(server_key)) => server_crt.map(|server_crt| open_ssl_files(server_crt, server_key.get_contents()).map(Some)),
+            (Some(server_crt), None) => server_crt.map(|_| Err(HappyServerError::MissingKey)),
+            (None, Some(server_key)) => server_key.map(|_| Err(HappyServerError::MissingCrt)),
+            (None, None) => ParameterSource::Default(Ok(None)),
+        };
+        // The default port depends on whether the server is delivered over TLS.
+        let default_port = match ssl {
+            ParameterSource::Default(_) => DEFAULT_HTTP_PORT,
+            _ => DEFAULT_HTTPS_PORT,
+        };
+        let spa = match *self.spa.get_ref() {
+            true => {
+                let mut spa = Spa::default();
+                if let Some(spa_assets) = self.spa_assets {
+                    spa.asset_extensions = spa_assets.get_contents().iter()
+                        .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+                        .filter(|extension| !extension.is_empty())
+                        .collect();
                 }
-                None => match std::env::current_dir() {
-                    Ok(path) => ParameterSource::Default(Ok(path)),
-                    Err(_) => ParameterSource::Default(Err(()))
+                let distribution_dir = distribution_dir.get_ref().as_ref().ok();
+                match self.spa_index {
+                    Some(index_file) => index_file.map(|index_file| check_spa(Spa{index_file: PathBuf::from(index_file), ..spa}, distribution_dir)),
+                    None => ParameterSource::Default(check_spa(spa, distribution_dir)),
                 }
             },
+            false => ParameterSource::Default(Ok(None)),
+        };
+        let share = match self.share {
+            Some(files) => files.map(|files| check_share(files, distribution_dir.get_ref().as_ref().ok())),
+            None => ParameterSource::Default(Ok(Vec::new())),
+        };
+        // The random secret is reported on the shared files it was generated for.
+        let share_secret = match self.share_secret {
+            Some(secret) => secret.map(|secret| Ok(Some(secret.into_bytes()))),
+            None => share.as_ref().map(|files| match files {
+                Ok(files) if !files.is_empty() => random_secret().map(Some),
+                _ => Ok(None),
+            }),
+        };
+        HappyServerPreModel {
+            bind: match self.bind {
+                Some(host) => host.map(resolve_bind_address),
+                None => ParameterSource::Default(Ok(IpAddr::V4(DEFAULT_IPV4_ADDR))),
+            },
+            port: match self.port {
+                Some(port) => port.map(|port| match port.parse::<u16>() {
+                    Ok(port) => Ok(port),
+                    Err(_) => Err(HappyServerError::InvalidPort(port, default_port)),
+                }),
+                None => ParameterSource::Default(Ok(default_port))
+            },
+            distribution_dir,
             uri_prefix: match self.uri_prefix {
-                Some(uri_prefix) => {
-                    let dobule_slice = uri_prefix.find("//");
-                    let first_slice = uri_prefix.find("/");
-                    match (dobule_slice, first_slice) {                        
-                        (Some(_), _) => ParameterSource::CliArg(Err(())),
-                        (_, Some(0)) => ParameterSource::CliArg(Err(())),
-                        (None, _) => ParameterSource::CliArg(Ok(uri_prefix)),
-                    }
-                },
+                Some(uri_prefix) => uri_prefix.map(check_uri_prefix),
                 None => ParameterSource::Default(Ok("".to_string())),
-            }
+            },
+            listeners: match self.listen {
+                Some(listen) => listen.map(|listen| listen.into_iter().map(parse_listen).collect()),
+                None => ParameterSource::Default(Ok(Vec::new())),
+            },
+            auto_port: self.auto_port.get_contents(),
+            ssl,
+            index_files: self.index_files.map(ParameterSource::get_contents).unwrap_or_default(),
+            listing: !self.no_listing.get_contents(),
+            listing_template: match self.listing_template {
+                Some(path) => path.map(|path| std::fs::read_to_string(&path).map(Some).map_err(|e| HappyServerError::ListingTemplate(PathBuf::from(path), e))),
+                None => ParameterSource::Default(Ok(None)),
+            },
+            spa,
+            upload: self.upload.get_contents(),
+            upload_max_size: match self.upload_max_size {
+                Some(size) => size.map(parse_size),
+                None => ParameterSource::Default(Ok(DEFAULT_UPLOAD_MAX_SIZE)),
+            },
+            upload_max_request_size: match self.upload_max_request_size {
+                Some(size) => size.map(parse_size),
+                None => ParameterSource::Default(Ok(DEFAULT_UPLOAD_MAX_REQUEST_SIZE)),
+            },
+            upload_overwrite: match self.upload_overwrite {
+                Some(overwrite) => overwrite.map(parse_overwrite),
+                None => ParameterSource::Default(Ok(Overwrite::Reject)),
+            },
+            webdav: match self.webdav.get_contents() {
+                true => Some(WebDav{writable: self.webdav_write.get_contents()}),
+                false => None,
+            },
+            auth: match self.auth {
+                Some(users) => users.map(parse_auth),
+                None => ParameterSource::Default(Ok(Vec::new())),
+            },
+            htpasswd: match self.htpasswd {
+                Some(path) => path.map(read_htpasswd),
+                None => ParameterSource::Default(Ok(Vec::new())),
+            },
+            allow: match self.allow {
+                Some(ranges) => ranges.map(parse_ip_ranges),
+                None => ParameterSource::Default(Ok(Vec::new())),
+            },
+            deny: match self.deny {
+                Some(ranges) => ranges.map(parse_ip_ranges),
+                None => ParameterSource::Default(Ok(Vec::new())),
+            },
+            lan_only: self.lan_only.get_contents(),
+            watch: self.watch.get_contents(),
+            access_log: match self.access_log {
+                Some(format) => format.map(parse_log_format),
+                None => ParameterSource::Default(Ok(None)),
+            },
+            access_log_file: self.access_log_file.map(|path| PathBuf::from(path.get_contents())),
+            access_log_max_size: match self.access_log_max_size {
+                Some(size) => size.map(|size| parse_size(size).map(Some)),
+                None => ParameterSource::Default(Ok(None)),
+            },
+            access_log_daily: self.access_log_daily.get_contents(),
+            access_log_compress: self.access_log_compress.get_contents(),
+            access_log_max_files: match self.access_log_max_files {
+                Some(max_files) => max_files.map(parse_max_files),
+                None => ParameterSource::Default(Ok(None)),
+            },
+            share,
+            share_expires: match self.share_expires {
+                Some(duration) => duration.map(parse_duration),
+                None => ParameterSource::Default(Ok(DEFAULT_SHARE_EXPIRES)),
+            },
+            share_max_downloads: match self.share_max_downloads {
+                Some(max_downloads) => max_downloads.map(parse_max_downloads),
+                None => ParameterSource::Default(Ok(None)),
+            },
+            share_secret,
+            proxies: match self.proxy {
+                Some(rules) => rules.map(parse_proxies),
+                None => ParameterSource::Default(Ok(Vec::new())),
+            },
+            compress: self.compress.get_contents(),
+            compress_include: match self.compress_include {
+                Some(content_types) => content_types.map(check_content_types),
+                None => ParameterSource::Default(Ok(Vec::new())),
+            },
+            compress_exclude: match self.compress_exclude {
+                Some(content_types) => content_types.map(check_content_types),
+                None => ParameterSource::Default(Ok(Vec::new())),
+            },
+            compress_min_size: match self.compress_min_size {
+                Some(size) => size.map(parse_size),
+                None => ParameterSource::Default(Ok(DEFAULT_COMPRESS_MIN_SIZE)),
+            },
+            precompressed: self.precompressed.get_contents(),
+            config_file: self.config_file,
         }
     }
 }
 impl HappyServerPreModel {
-    pub fn to_server_builder(self) -> Result<HappyServerBuilder, ()> {
-        match (self.port, self.distribution_dir.get_contents(), self.uri_prefix.get_contents()) {
-            (Ok(port), Ok(path), Ok(uri_prefix)) => Ok(
-                HappyServerBuilder{
-                    socket_addr: std::net::SocketAddrV4::new(DEFAULT_IPV4_ADDR, port),
-                    distribution_dir: path,
-                    uri_prefix: uri_prefix,
-                    ssl: None,
-                }
-            ),
-            _ => Err(())
+    /// # Complete the parameters
+    /// The listener made from `bind` and `port` is left out if other listeners are given
+    /// and neither `bind` nor `port` was specified.
+    /// If some parameters are invalid, the first error is returned.
+    /// All of them can be displayed beforehand with `HappyServerModelViewer`.
+    /// The pages of the server, such as the listing, are in the given language.
+    pub fn into_server_builder(self, language: Language) -> Result<HappyServerBuilder, HappyServerError> {
+        if let Some(Err(e)) = self.config_file {
+            return Err(e);
+        }
+        let default_listener = match (&self.bind, &self.port, &self.listeners) {
+            (ParameterSource::Default(_), ParameterSource::Default(_), ParameterSource::Default(_)) => true,
+            (ParameterSource::Default(_), ParameterSource::Default(_), _) => false,
+            _ => true,
+        };
+        let bind = self.bind.get_contents()?;
+        let port = self.port.get_contents()?;
+        let distribution_dir = self.distribution_dir.get_contents()?;
+        let uri_prefix = self.uri_prefix.get_contents()?;
+        let ssl = self.ssl.get_contents()?;
+        let spa = self.spa.get_contents()?;
+        let listing_template = self.listing_template.get_contents()?;
+        let mut users = self.auth.get_contents()?;
+        users.extend(self.htpasswd.get_contents()?);
+        let mut allow = self.allow.get_contents()?;
+        let deny = self.deny.get_contents()?;
+        if self.lan_only {
+            allow.extend(IpFilter::lan_only().allow);
+        }
+        let access_log_file = self.access_log_file;
+        let rotation = Rotation{
+            max_size: self.access_log_max_size.get_contents()?,
+            daily: self.access_log_daily,
+            compress: self.access_log_compress,
+            max_files: self.access_log_max_files.get_contents()?,
+        };
+        let access_log = self.access_log.get_contents()?.map(|format| AccessLog{format, file: access_log_file, rotation});
+        let share_files = self.share.get_contents()?;
+        let share_expires = self.share_expires.get_contents()?;
+        let share_max_downloads = self.share_max_downloads.get_contents()?;
+        let share_secret = self.share_secret.get_contents()?;
+        let proxies = self.proxies.get_contents()?;
+        let compression = Compression{
+            include: self.compress_include.get_contents()?,
+            exclude: self.compress_exclude.get_contents()?,
+            min_size: self.compress_min_size.get_contents()?,
+        };
+        let upload = Upload{
+            max_size: self.upload_max_size.get_contents()?,
+            max_request_size: self.upload_max_request_size.get_contents()?,
+            overwrite: self.upload_overwrite.get_contents()?,
+        };
+        let mut listeners = Vec::new();
+        if default_listener {
+            listeners.push(Listener{addr: ListenAddr::Tcp(SocketAddr::new(bind, port)), tls: ssl.is_some()});
         }
+        listeners.extend(self.listeners.get_contents()?);
+        Ok(HappyServerBuilder{
+            listeners,
+            distribution_dir,
+            uri_prefix,
+            ssl,
+            auto_port: self.auto_port,
+            index_files: self.index_files,
+            listing: self.listing,
+            listing_template,
+            language,
+            spa,
+            upload: if self.upload {Some(upload)} else {None},
+            webdav: self.webdav,
+            auth: if users.is_empty() {None} else {Some(Auth{users})},
+            ip_filter: if allow.is_empty() && deny.is_empty() {None} else {Some(IpFilter{allow, deny})},
+            share: share(share_files, share_expires, share_max_downloads, share_secret)?,
+            watch: self.watch,
+            access_log,
+            compression: if self.compress {Some(compression)} else {None},
+            precompressed: self.precompressed,
+            proxies,
+            // The config file may hold passwords and the secret of the share links.
+            protected_files: self.config_file.and_then(Result::ok).into_iter().collect(),
+            on_event: None,
+        })
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_listen_forms() {
+        let listener = parse_listen("8080".to_string()).ok().unwrap();
+        assert_eq!(listener.url(""), "http://localhost:8080/");
+        let listener = parse_listen("https://[::1]:8443".to_string()).ok().unwrap();
+        assert_eq!(listener.url("app"), "https://[::1]:8443/app");
+        let listener = parse_listen("https://127.0.0.1".to_string()).ok().unwrap();
+        assert_eq!(listener.url(""), "https://127.0.0.1/");
+        assert!(parse_listen("http://no such host".to_string()).is_err());
+    }
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("1000".to_string()).ok(), Some(1000));
+        assert_eq!(parse_size("100M".to_string()).ok(), Some(100 * 1024 * 1024));
+        assert_eq!(parse_size("2 GiB".to_string()).ok(), Some(2 << 30));
+        assert_eq!(parse_size("64kb".to_string()).ok(), Some(64 * 1024));
+        assert!(parse_size("1.5G".to_string()).is_err());
+        assert!(parse_size("M".to_string()).is_err());
+    }
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("90".to_string()).ok(), Some(90));
+        assert_eq!(parse_duration("30m".to_string()).ok(), Some(30 * 60));
+        assert_eq!(parse_duration("7D".to_string()).ok(), Some(7 * 24 * 60 * 60));
+        assert!(parse_duration("1.5h".to_string()).is_err());
+        assert!(parse_duration("h".to_string()).is_err());
+        let files = vec!["docs//report.pdf".to_string(), "../secret".to_string()];
+        assert_eq!(check_share(files[..1].to_vec(), None).ok(), Some(vec!["docs/report.pdf".to_string()]));
+        assert!(check_share(files, None).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::super::error::HappyServerError;
+use super::super::server_core::interface_addrs;
+use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, SanType};
+use std::fs;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+const CACHED_CRT_FILE: &str = "happy_server_crt.pem";
+const CACHED_KEY_FILE: &str = "happy_server_key.pem";
+const CACHED_SANS_FILE: &str = "happy_server_sans.txt";
+
+/// # Development certificate generated on the fly
+pub struct SelfSigned {
+    pub server_crt: String,
+    pub server_key: String,
+    pub fingerprint: String,
+}
+
+/// # Names the certificate is valid for
+/// localhost, the loopback addresses and the addresses of every non-loopback interface.
+fn subject_alt_names() -> Vec<SanType> {
+    let mut sans = vec![
+        SanType::DnsName("localhost".to_string()),
+        SanType::IpAddress(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        SanType::IpAddress(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+    ];
+    // If the interfaces cannot be listed, the certificate is still usable from this machine.
+    for (_, ip) in interface_addrs() {
+        let san = SanType::IpAddress(ip);
+        if !sans.contains(&san) {
+            sans.push(san);
+        }
+    }
+    sans
+}
+
+/// # SAN list as text
+/// Used to detect that a cached certificate no longer covers the current addresses.
+fn sans_to_string(sans: &[SanType]) -> String {
+    sans.iter().map(|san| match san {
+        SanType::DnsName(name) => format!("DNS:{}\n", name),
+        SanType::IpAddress(ip) => format!("IP:{}\n", ip),
+        other => format!("{:?}\n", other),
+    }).collect()
+}
+
+/// # SHA-256 fingerprint of the first certificate in a PEM string
+/// Formatted as colon separated upper case hex, the way browsers display it.
+pub fn fingerprint(server_crt: &str) -> Option<String> {
+    let certs = rustls::internal::pemfile::certs(&mut server_crt.as_bytes()).ok()?;
+    let cert = certs.first()?;
+    let digest = ring::digest::digest(&ring::digest::SHA256, &cert.0);
+    Some(digest.as_ref().iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(":"))
+}
+
+fn generate(sans: Vec<SanType>) -> Result<SelfSigned, HappyServerError> {
+    let mut params = CertificateParams::default();
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, "happy_server development certificate");
+    params.distinguished_name = distinguished_name;
+    params.subject_alt_names = sans;
+    let cert = Certificate::from_params(params).map_err(HappyServerError::SelfSigned)?;
+    let server_crt = cert.serialize_pem().map_err(HappyServerError::SelfSigned)?;
+    let server_key = cert.serialize_private_key_pem();
+    // The fingerprint is taken from the PEM itself, because each serialization is signed again.
+    let fingerprint = fingerprint(&server_crt).unwrap_or_default();
+    Ok(SelfSigned{server_crt, server_key, fingerprint})
+}
+
+/// # Read a previously generated certificate from the cache directory
+/// Returns None if there is no cache or it was made for other addresses.
+fn load_cache(cache_dir: &Path, sans: &str) -> Option<SelfSigned> {
+    let cached_sans = fs::read_to_string(cache_dir.join(CACHED_SANS_FILE)).ok()?;
+    if cached_sans != sans {
+        return None;
+    }
+    let server_crt = fs::read_to_string(cache_dir.join(CACHED_CRT_FILE)).ok()?;
+    let server_key = fs::read_to_string(cache_dir.join(CACHED_KEY_FILE)).ok()?;
+    let fingerprint = fingerprint(&server_crt)?;
+    Some(SelfSigned{server_crt, server_key, fingerprint})
+}
+
+/// # Write a file that only the owner can read
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    // The mode is only applied to new files, a key saved by an older version may still be readable by others.
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    fs::File::create(path)?.write_all(contents.as_bytes())
+}
+
+fn save_cache(cache_dir: &Path, sans: &str, self_signed: &SelfSigned) -> Result<(), HappyServerError> {
+    let write = |file_name: &str, contents: &str, write_file: fn(&Path, &str) -> io::Result<()>| {
+        let path = cache_dir.join(file_name);
+        write_file(&path, contents).map_err(|e| HappyServerError::CertCache(path, e))
+    };
+    fs::create_dir_all(cache_dir).map_err(|e| HappyServerError::CertCache(cache_dir.to_path_buf(), e))?;
+    write(CACHED_CRT_FILE, &self_signed.server_crt, |path, contents| fs::write(path, contents))?;
+    write(CACHED_KEY_FILE, &self_signed.server_key, write_private)?;
+    write(CACHED_SANS_FILE, sans, |path, contents| fs::write(path, contents))
+}
+
+/// # Prepare a self-signed certificate
+/// If a cache directory is given, a certificate saved there is reused as long as it covers the same addresses.
+/// Otherwise a new one is generated and saved.
+pub fn load_or_generate(cache_dir: Option<&Path>) -> Result<SelfSigned, HappyServerError> {
+    let sans = subject_alt_names();
+    let sans_string = sans_to_string(&sans);
+    match cache_dir {
+        Some(cache_dir) => match load_cache(cache_dir, &sans_string) {
+            Some(self_signed) => Ok(self_signed),
+            None => {
+                let self_signed = generate(sans)?;
+                save_cache(cache_dir, &sans_string, &self_signed)?;
+                Ok(self_signed)
+            }
+        },
+        None => generate(sans),
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::event::{EventHandler, ServerEvent};
+use super::listing;
+use super::log_file::{LogFile, Rotation};
+use actix_web::body::{BodySize, MessageBody, ResponseBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{http::header, web::Bytes, Error};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use serde::Serialize;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// # Format of the access log lines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Apache common log format, followed by the latency in milliseconds.
+    Common,
+    /// Common log format with the referer and user agent, followed by the latency in milliseconds.
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+/// # Write a line per request
+/// The line is written when the response has been sent, so that the bytes and the latency cover the whole body.
+/// Without a file, the lines are passed to the event handler as `ServerEvent::Access`.
+#[derive(Clone, Debug)]
+pub struct AccessLog {
+    pub format: LogFormat,
+    /// File the lines are appended to.
+    pub file: Option<PathBuf>,
+    /// When the file is started over, see `Rotation`.
+    pub rotation: Rotation,
+}
+
+/// # What is logged of a request
+#[derive(Clone, Debug, Serialize)]
+pub struct AccessRecord {
+    /// Start of the request, in RFC 3339.
+    pub time: String,
+    /// Address of the connection, "-" for a Unix domain socket.
+    pub client: String,
+    /// User name of basic authentication, if given.
+    pub user: Option<String>,
+    pub method: String,
+    /// Path and query.
+    pub uri: String,
+    pub protocol: String,
+    pub status: u16,
+    /// Bytes of the body that were sent.
+    pub bytes: u64,
+    /// From the request until the last byte of the body, in milliseconds.
+    pub latency_ms: f64,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    #[serde(skip)]
+    unix_time: u64,
+}
+
+impl AccessRecord {
+    pub fn format(&self, format: LogFormat) -> String {
+        let common = format!("{} - {} [{}] \"{} {} {}\" {} {}", self.client, self.user.as_deref().unwrap_or("-"), clf_time(self.unix_time),
+            self.method, self.uri, self.protocol, self.status, self.bytes);
+        match format {
+            LogFormat::Common => format!("{} {:.3}", common, self.latency_ms),
+            LogFormat::Combined => format!("{} \"{}\" \"{}\" {:.3}", common, quote(self.referer.as_deref()), quote(self.user_agent.as_deref()), self.latency_ms),
+            LogFormat::Json => serde_json::to_string(self).unwrap_or_default(),
+        }
+    }
+}
+
+/// # Date and time of the common log format, e.g. "10/Jul/2021:12:34:56 +0000"
+fn clf_time(unix_time: u64) -> String {
+    let (year, month, day, seconds) = listing::civil_time(unix_time);
+    format!("{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000", day, MONTHS[(month - 1) as usize], year, seconds / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+/// # Header value inside the double quotes of a log line
+fn quote(value: Option<&str>) -> String {
+    match value {
+        Some(value) => value.replace('\\', "\\\\").replace('"', "\\\""),
+        None => "-".to_string(),
+    }
+}
+
+/// # User name of an `Authorization: Basic` header
+fn basic_user(req: &ServiceRequest) -> Option<String> {
+    let encoded = req.headers().get(header::AUTHORIZATION)?.as_bytes().strip_prefix(b"Basic ")?;
+    let credentials = base64::decode(encoded).ok()?;
+    let credentials = String::from_utf8_lossy(&credentials);
+    credentials.split_once(':').map(|(user, _)| user.to_string())
+}
+
+fn header_value(req: &ServiceRequest, name: header::HeaderName) -> Option<String> {
+    req.headers().get(name).and_then(|value| value.to_str().ok()).map(String::from)
+}
+
+/// # Where the lines go
+#[derive(Clone)]
+enum Output {
+    File(Arc<Mutex<LogFile>>),
+    Event(EventHandler),
+    None,
+}
+
+impl Output {
+    fn write(&self, line: String) {
+        match self {
+            // A failed write must not fail the request, the next line is tried again.
+            Self::File(file) => if let Ok(mut file) = file.lock() {
+                let _ = file.write_line(&line);
+            },
+            Self::Event(on_event) => on_event(ServerEvent::Access{line}),
+            Self::None => (),
+        }
+    }
+}
+
+/// # Middleware that writes the access log
+/// Requests pass through unchanged without `AccessLog`.
+#[derive(Clone)]
+pub struct AccessLogger {
+    format: Option<LogFormat>,
+    output: Output,
+}
+
+impl AccessLogger {
+    /// # Open the log file
+    pub fn new(access_log: Option<AccessLog>, on_event: Option<EventHandler>) -> io::Result<Self> {
+        let (format, output) = match (access_log, on_event) {
+            (Some(AccessLog{format, file: Some(path), rotation}), _) => {
+                let file = LogFile::open(path, rotation)?;
+                (Some(format), Output::File(Arc::new(Mutex::new(file))))
+            },
+            (Some(AccessLog{format, file: None, ..}), Some(on_event)) => (Some(format), Output::Event(on_event)),
+            _ => (None, Output::None),
+        };
+        Ok(Self{format, output})
+    }
+}
+
+impl<S, B> Transform<S> for AccessLogger
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<LoggedBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AccessLoggerMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AccessLoggerMiddleware{service, access_logger: self.clone()})
+    }
+}
+
+pub struct AccessLoggerMiddleware<S> {
+    service: S,
+    access_logger: AccessLogger,
+}
+
+impl<S, B> Service for AccessLoggerMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<LoggedBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let format = self.access_logger.format;
+        let output = self.access_logger.output.clone();
+        let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default();
+        let record = format.map(|_| AccessRecord{
+            time: listing::format_rfc3339(unix_time),
+            client: req.peer_addr().map(|peer_addr| peer_addr.ip().to_string()).unwrap_or_else(|| "-".to_string()),
+            user: basic_user(&req),
+            method: req.method().to_string(),
+            uri: req.uri().path_and_query().map(|path_and_query| path_and_query.to_string()).unwrap_or_default(),
+            protocol: format!("{:?}", req.version()),
+            status: 0,
+            bytes: 0,
+            latency_ms: 0.0,
+            referer: header_value(&req, header::REFERER),
+            user_agent: header_value(&req, header::USER_AGENT),
+            unix_time,
+        });
+        let start = Instant::now();
+        let response = self.service.call(req);
+        Box::pin(async move {
+            let response = response.await?;
+            let status = response.status().as_u16();
+            Ok(response.map_body(move |_, body| ResponseBody::Body(LoggedBody{
+                body,
+                log: format.zip(record).map(|(format, record)| (format, AccessRecord{status, ..record}, output)),
+                start,
+            })))
+        })
+    }
+}
+
+/// # Body that writes the log line when it has been sent or dropped
+pub struct LoggedBody<B> {
+    body: ResponseBody<B>,
+    log: Option<(LogFormat, AccessRecord, Output)>,
+    start: Instant,
+}
+
+impl<B: MessageBody + Unpin> MessageBody for LoggedBody<B> {
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Error>>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.body).poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some((_, record, _)) = &mut this.log {
+                    record.bytes += chunk.len() as u64;
+                }
+                Poll::Ready(Some(Ok(chunk)))
+            },
+            poll => poll,
+        }
+    }
+}
+
+impl<B> Drop for LoggedBody<B> {
+    fn drop(&mut self) {
+        if let Some((format, mut record, output)) = self.log.take() {
+            // rounded to microseconds, so that the JSON lines stay short
+            record.latency_ms = (self.start.elapsed().as_secs_f64() * 1_000_000.0).round() / 1000.0;
+            // The body of a HEAD response is not sent.
+            if record.method == "HEAD" {
+                record.bytes = 0;
+            }
+            output.write(record.format(format));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn log_formats() {
+        let record = AccessRecord{
+            time: listing::format_rfc3339(1625920496),
+            client: "192.168.0.10".to_string(),
+            user: None,
+            method: "GET".to_string(),
+            uri: "/index.html?a=1".to_string(),
+            protocol: "HTTP/1.1".to_string(),
+            status: 200,
+            bytes: 1234,
+            latency_ms: 0.5,
+            referer: None,
+            user_agent: Some("curl \"7\"".to_string()),
+            unix_time: 1625920496,
+        };
+        assert_eq!(record.format(LogFormat::Common), "192.168.0.10 - - [10/Jul/2021:12:34:56 +0000] \"GET /index.html?a=1 HTTP/1.1\" 200 1234 0.500");
+        assert!(record.format(LogFormat::Combined).ends_with("1234 \"-\" \"curl \\\"7\\\"\" 0.500"));
+        assert!(record.format(LogFormat::Json).starts_with("{\"time\":\"2021-07-10T12:34:56Z\",\"client\":\"192.168.0.10\",\"user\":null,"));
+    }
+}
@@ -0,0 +1,239 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::listing::civil_time;
+use super::protect::ProtectedFiles;
+use super::zip::{dos_time, ZipStream};
+use actix_web::{http::header, web::Bytes, HttpResponse};
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+/// Size of the chunks sent to the client.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Number of chunks buffered before the archiving thread waits for the client.
+const CHANNEL_CAPACITY: usize = 8;
+/// Archives written at the same time, further requests are answered with 503 Service Unavailable.
+const MAX_ARCHIVES: usize = 16;
+
+/// Number of archiving threads running.
+static ARCHIVES: AtomicUsize = AtomicUsize::new(0);
+
+/// # Place of an archiving thread, given back when the thread ends
+struct ArchiveSlot;
+
+impl ArchiveSlot {
+    fn take() -> Option<Self> {
+        ARCHIVES.fetch_update(Ordering::AcqRel, Ordering::Acquire, |running| match running < MAX_ARCHIVES {
+            true => Some(running + 1),
+            false => None,
+        }).ok().map(|_| Self)
+    }
+}
+
+impl Drop for ArchiveSlot {
+    fn drop(&mut self) {
+        ARCHIVES.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// # Archive formats of `?download=`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// # Format requested with `?download=zip` or `?download=tar.gz`
+    pub fn from_query(query: &str) -> Option<Self> {
+        query.split('&').find_map(|pair| match pair {
+            "download=zip" => Some(Self::Zip),
+            "download=tar.gz" | "download=tgz" => Some(Self::TarGz),
+            _ => None,
+        })
+    }
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::TarGz => "tar.gz",
+        }
+    }
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// # Stream the directory as an archive
+/// The archive is written on a thread of its own while it is sent, nothing is stored on disk.
+/// The thread waits for slow clients, so it is not taken from the blocking pool that serves the files,
+/// and the number of these threads is limited by `MAX_ARCHIVES`.
+/// Like normal serving, hidden and protected files are left out and symbolic links are followed,
+/// except for links back to a directory that contains them.
+pub fn response(dir: &Path, format: ArchiveFormat, protected_files: ProtectedFiles) -> HttpResponse {
+    let root_name = dir.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| "download".to_string());
+    let file_name = format!("{}.{}", root_name, format.extension());
+    let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    let slot = match ArchiveSlot::take() {
+        Some(slot) => slot,
+        None => return HttpResponse::ServiceUnavailable()
+            .header(header::RETRY_AFTER, "10")
+            .content_type("text/plain; charset=utf-8")
+            .body("503 Service Unavailable"),
+    };
+    let dir = dir.to_path_buf();
+    std::thread::spawn(move || {
+        let _slot = slot;
+        let writer = ChannelWriter{sender, buffer: Vec::with_capacity(CHUNK_SIZE)};
+        // On an error, e.g. when the client closes the connection, the archive is just cut off.
+        let _ = match format {
+            ArchiveFormat::Zip => write_zip(writer, &dir, &root_name, &protected_files),
+            ArchiveFormat::TarGz => write_tar_gz(writer, &dir, &root_name, &protected_files),
+        };
+    });
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename*=UTF-8''{}", utf8_percent_encode(&file_name, NON_ALPHANUMERIC)))
+        .streaming(receiver.map(Ok::<Bytes, actix_web::Error>))
+}
+
+/// # Writer that sends the written bytes to the response in chunks
+struct ChannelWriter {
+    sender: mpsc::Sender<Bytes>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE)));
+        futures::executor::block_on(self.sender.send(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the client closed the connection"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+/// # File or directory to put in the archive
+struct ArchiveEntry {
+    path: PathBuf,
+    /// Path in the archive, with "/" separators and a trailing "/" for directories.
+    name: String,
+    metadata: Metadata,
+}
+
+impl ArchiveEntry {
+    fn mtime(&self) -> u64 {
+        self.metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default()
+    }
+    #[cfg(unix)]
+    fn mode(&self) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        self.metadata.permissions().mode() & 0o777
+    }
+    #[cfg(not(unix))]
+    fn mode(&self) -> u32 {
+        if self.metadata.is_dir() {0o755} else {0o644}
+    }
+}
+
+/// # Walk the directory tree in name order
+/// `visit` is called for each directory before its contents.
+fn walk(dir: &Path, name: &str, ancestors: &mut Vec<PathBuf>, protected_files: &ProtectedFiles, visit: &mut impl FnMut(&ArchiveEntry) -> io::Result<()>) -> io::Result<()> {
+    let canonical = dir.canonicalize()?;
+    if ancestors.contains(&canonical) {
+        // symbolic link loop
+        return Ok(());
+    }
+    visit(&ArchiveEntry{path: dir.to_path_buf(), name: format!("{}/", name), metadata: fs::metadata(dir)?})?;
+    ancestors.push(canonical);
+    let mut children: Vec<_> = fs::read_dir(dir)?.filter_map(Result::ok)
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter(|entry| !protected_files.contains(&entry.path()))
+        .collect();
+    children.sort_by_key(|entry| entry.file_name());
+    for child in children {
+        let path = child.path();
+        let child_name = format!("{}/{}", name, child.file_name().to_string_lossy());
+        // follows symbolic links, broken ones are left out
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            walk(&path, &child_name, ancestors, protected_files, visit)?;
+        } else if metadata.is_file() {
+            visit(&ArchiveEntry{path, name: child_name, metadata})?;
+        }
+    }
+    ancestors.pop();
+    Ok(())
+}
+
+fn write_zip(writer: ChannelWriter, dir: &Path, root_name: &str, protected_files: &ProtectedFiles) -> io::Result<()> {
+    let mut zip = ZipStream::new(writer);
+    walk(dir, root_name, &mut Vec::new(), protected_files, &mut |entry| {
+        let dos_time = dos_time(civil_time(entry.mtime()));
+        match entry.metadata.is_dir() {
+            true => zip.add_dir(&entry.name, dos_time, entry.mode()),
+            // A file that grows while it is archived is cut at the size it had in the listing.
+            false => match File::open(&entry.path) {
+                Ok(file) => zip.add_file(&entry.name, &mut file.take(entry.metadata.len()), entry.metadata.len(), dos_time, entry.mode()),
+                // unreadable files are left out, like a 404 in normal serving
+                Err(_) => Ok(()),
+            },
+        }
+    })?;
+    zip.finish()?.flush()
+}
+
+fn write_tar_gz(writer: ChannelWriter, dir: &Path, root_name: &str, protected_files: &ProtectedFiles) -> io::Result<()> {
+    let mut tar = tar::Builder::new(flate2::write::GzEncoder::new(writer, flate2::Compression::default()));
+    walk(dir, root_name, &mut Vec::new(), protected_files, &mut |entry| {
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(entry.mtime());
+        header.set_mode(entry.mode());
+        match entry.metadata.is_dir() {
+            true => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_size(0);
+                tar.append_data(&mut header, &entry.name, io::empty())
+            },
+            false => match File::open(&entry.path) {
+                Ok(file) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(entry.metadata.len());
+                    // The size is in the header, so a file that changes while it is archived is cut or padded with zeros to it.
+                    let len = entry.metadata.len();
+                    tar.append_data(&mut header, &entry.name, file.take(len).chain(io::repeat(0)).take(len))
+                },
+                Err(_) => Ok(()),
+            },
+        }
+    })?;
+    tar.into_inner()?.finish()?.flush()
+}
@@ -0,0 +1,211 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use super::share::Shared;
+use actix_web::{http::header, Error, HttpMessage, HttpResponse};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use futures::future::{ok, Either, Ready};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// Realm shown by browsers in the login dialog.
+const REALM: &str = "happy server";
+/// Verified `Authorization` headers kept, so that hashes are not checked on every request.
+const VERIFIED_CACHE_SIZE: usize = 1024;
+
+/// # Marks a request whose `Authorization` header was checked here, so that it is not passed on to a proxy target
+pub(super) struct Authenticated;
+
+/// # Password of a user, in plain text or hashed
+#[derive(Clone, Debug)]
+pub enum Password {
+    Plain(String),
+    /// "$2a$", "$2b$" or "$2y$", e.g. from `htpasswd -B`.
+    Bcrypt(String),
+    /// PHC string starting with "$argon2".
+    Argon2(String),
+}
+
+impl Password {
+    /// # Recognize a hash by its prefix
+    /// Anything that does not look like a hash is a plain text password.
+    /// None for other hashes, e.g. MD5 ("$apr1$") or SHA-1 ("{SHA}"), which are not supported.
+    pub fn parse(password: &str) -> Option<Self> {
+        if password.starts_with("$2a$") || password.starts_with("$2b$") || password.starts_with("$2y$") {
+            Some(Self::Bcrypt(password.to_string()))
+        } else if password.starts_with("$argon2") {
+            Some(Self::Argon2(password.to_string()))
+        } else if password.starts_with('$') || password.starts_with("{SHA}") {
+            None
+        } else {
+            Some(Self::Plain(password.to_string()))
+        }
+    }
+    pub fn verify(&self, password: &str) -> bool {
+        match self {
+            Self::Plain(plain) => ring::constant_time::verify_slices_are_equal(plain.as_bytes(), password.as_bytes()).is_ok(),
+            Self::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            Self::Argon2(hash) => match PasswordHash::new(hash) {
+                Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+/// # Users allowed to access the server with HTTP Basic authentication
+/// Every request has to carry the user name and password of one of them, including WebDAV and uploads.
+/// Only requests with a valid share link need none.
+#[derive(Clone, Debug, Default)]
+pub struct Auth {
+    pub users: Vec<(String, Password)>,
+}
+
+impl Auth {
+    /// # Allow a user
+    pub fn user(mut self, name: impl Into<String>, password: Password) -> Self {
+        self.users.push((name.into(), password));
+        self
+    }
+    /// # Read the users of an htpasswd file
+    /// Lines are "user:password" or "user:hash", empty lines and lines starting with "#" are skipped.
+    /// On an invalid line, its number (from 1) is returned.
+    pub fn parse_htpasswd(contents: &str) -> Result<Vec<(String, Password)>, usize> {
+        contents.lines().enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(index, line)| match line.trim_end().split_once(':') {
+                Some((user, password)) if !user.is_empty() => Password::parse(password).map(|password| (user.to_string(), password)).ok_or(index + 1),
+                _ => Err(index + 1),
+            })
+            .collect()
+    }
+    /// # Check the value of an `Authorization` header
+    fn verify(&self, authorization: &[u8]) -> bool {
+        let credentials = match authorization.strip_prefix(b"Basic ").and_then(|encoded| base64::decode(encoded).ok()) {
+            Some(credentials) => credentials,
+            None => return false,
+        };
+        let credentials = String::from_utf8_lossy(&credentials);
+        match credentials.split_once(':') {
+            Some((user, password)) => self.users.iter()
+                .filter(|(name, _)| name == user)
+                .any(|(_, hash)| hash.verify(password)),
+            None => false,
+        }
+    }
+}
+
+/// # Middleware that asks for a user name and password
+/// Requests pass through unchanged without `Auth`.
+#[derive(Clone)]
+pub struct Authentication {
+    auth: Option<Arc<Auth>>,
+    /// SHA-256 of the `Authorization` headers that were accepted.
+    verified: Arc<Mutex<HashSet<Vec<u8>>>>,
+}
+
+impl Authentication {
+    pub fn new(auth: Option<Auth>) -> Self {
+        Self{auth: auth.map(Arc::new), verified: Arc::new(Mutex::new(HashSet::new()))}
+    }
+    fn authorized(&self, req: &ServiceRequest) -> bool {
+        let auth = match &self.auth {
+            Some(auth) => auth,
+            None => return true,
+        };
+        if req.extensions().contains::<Shared>() {
+            return true;
+        }
+        let authorization = match req.headers().get(header::AUTHORIZATION) {
+            Some(authorization) => authorization.as_bytes(),
+            None => return false,
+        };
+        let digest = ring::digest::digest(&ring::digest::SHA256, authorization).as_ref().to_vec();
+        let authorized = self.verified.lock().map(|verified| verified.contains(&digest)).unwrap_or(false) || auth.verify(authorization);
+        if authorized {
+            req.extensions_mut().insert(Authenticated);
+            if let Ok(mut verified) = self.verified.lock() {
+                if verified.len() >= VERIFIED_CACHE_SIZE {
+                    verified.clear();
+                }
+                verified.insert(digest);
+            }
+        }
+        authorized
+    }
+}
+
+impl<S, B> Transform<S> for Authentication
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthenticationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthenticationMiddleware{service, authentication: self.clone()})
+    }
+}
+
+pub struct AuthenticationMiddleware<S> {
+    service: S,
+    authentication: Authentication,
+}
+
+impl<S, B> Service for AuthenticationMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if self.authentication.authorized(&req) {
+            return Either::Left(self.service.call(req));
+        }
+        let response = HttpResponse::Unauthorized()
+            .header(header::WWW_AUTHENTICATE, format!("Basic realm=\"{}\", charset=\"UTF-8\"", REALM))
+            .content_type("text/plain; charset=utf-8")
+            .body("401 Unauthorized");
+        Either::Right(ok(req.into_response(response).map_body(|_, body| body.into_body())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verify_passwords() {
+        let htpasswd = format!("# users\nalice:secret\n\nbob:{}\n", bcrypt::hash("hunter2", 4).unwrap());
+        let auth = Auth{users: Auth::parse_htpasswd(&htpasswd).unwrap()};
+        assert!(auth.verify(format!("Basic {}", base64::encode("alice:secret")).as_bytes()));
+        assert!(auth.verify(format!("Basic {}", base64::encode("bob:hunter2")).as_bytes()));
+        assert!(!auth.verify(format!("Basic {}", base64::encode("bob:secret")).as_bytes()));
+        assert!(!auth.verify(b"Bearer token"));
+        assert_eq!(Auth::parse_htpasswd("carol:$apr1$abc$def").err(), Some(1));
+        assert_eq!(Auth::parse_htpasswd("# comment\nno colon").err(), Some(2));
+        use argon2::PasswordHasher;
+        let argon2 = Argon2::default().hash_password(b"correct horse", "c29tZXNhbHQ").unwrap().to_string();
+        let password = Password::parse(&argon2).unwrap();
+        assert!(matches!(password, Password::Argon2(_)));
+        assert!(password.verify("correct horse"));
+        assert!(!password.verify("battery staple"));
+    }
+}
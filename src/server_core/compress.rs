@@ -0,0 +1,437 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::upload;
+use actix_files::NamedFile;
+use actix_web::body::{BodySize, MessageBody, ResponseBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::{http::Method, http::StatusCode, web::Bytes, Error, HttpResponse};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Content types compressed by default. "text/*" matches every subtype.
+pub const DEFAULT_COMPRESS_TYPES: [&str; 8] = [
+    "text/*", "application/javascript", "application/json", "application/xml",
+    "application/wasm", "application/manifest+json", "image/svg+xml", "image/x-icon",
+];
+/// Content types never compressed, in addition to `Compression::exclude`.
+/// Events have to reach the browser one by one, as they are sent.
+const DEFAULT_COMPRESS_EXCLUDE: [&str; 1] = ["text/event-stream"];
+/// Smaller bodies are sent as they are, compression would hardly save anything.
+pub const DEFAULT_COMPRESS_MIN_SIZE: u64 = 1024;
+
+/// # Content codings, in the order preferred when the client accepts several equally
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+const ENCODINGS: [Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+impl Encoding {
+    /// # Token of `Accept-Encoding` and `Content-Encoding`
+    pub fn token(self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+        }
+    }
+    /// # Extension of a precompressed file, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Zstd => "zst",
+            Self::Gzip => "gz",
+        }
+    }
+}
+
+/// # Compress responses on the fly
+/// The coding is chosen by the `Accept-Encoding` of the request.
+/// Only complete responses (200) are compressed, not ranges, and not responses that are already encoded.
+#[derive(Clone, Debug)]
+pub struct Compression {
+    /// Content types to compress, e.g. "text/*" or "application/json". `DEFAULT_COMPRESS_TYPES` if empty.
+    pub include: Vec<String>,
+    /// Content types never compressed, even if included. `DEFAULT_COMPRESS_EXCLUDE` is excluded as well.
+    pub exclude: Vec<String>,
+    /// Bodies of a known size below this many bytes are not compressed.
+    pub min_size: u64,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            include: DEFAULT_COMPRESS_TYPES.iter().map(|content_type| content_type.to_string()).collect(),
+            exclude: Vec::new(),
+            min_size: DEFAULT_COMPRESS_MIN_SIZE,
+        }
+    }
+}
+
+impl Compression {
+    /// # Whether responses of the content type are compressed
+    pub fn compresses(&self, content_type: &str) -> bool {
+        let included = match self.include.is_empty() {
+            true => DEFAULT_COMPRESS_TYPES.iter().any(|pattern| matches_type(pattern, content_type)),
+            false => self.include.iter().any(|pattern| matches_type(pattern, content_type)),
+        };
+        let excluded = DEFAULT_COMPRESS_EXCLUDE.iter().any(|pattern| matches_type(pattern, content_type))
+            || self.exclude.iter().any(|pattern| matches_type(pattern, content_type));
+        included && !excluded
+    }
+}
+
+/// # Whether a content type such as "text/html; charset=utf-8" matches "text/html", "text/*" or "*/*"
+fn matches_type(pattern: &str, content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    let pattern = pattern.trim();
+    match pattern.strip_suffix("/*") {
+        Some("*") => true,
+        Some(type_) => essence.split('/').next().map(|essence_type| essence_type.eq_ignore_ascii_case(type_)).unwrap_or(false),
+        None => essence.eq_ignore_ascii_case(pattern),
+    }
+}
+
+/// # Coding to use among the available ones, by the q-values of `Accept-Encoding`
+/// None if the client accepts none of them.
+fn negotiate(accept_encoding: &str, available: &[Encoding]) -> Option<Encoding> {
+    let mut wildcard = 0.0;
+    let mut qualities: Vec<(&str, f32)> = Vec::new();
+    for coding in accept_encoding.split(',') {
+        let mut params = coding.split(';');
+        let token = params.next().unwrap_or_default().trim();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q=").or_else(|| param.trim().strip_prefix("Q=")))
+            .find_map(|quality| quality.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match token {
+            "*" => wildcard = quality,
+            "x-gzip" => qualities.push(("gzip", quality)),
+            "" => (),
+            token => qualities.push((token, quality)),
+        }
+    }
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in ENCODINGS.iter().filter(|encoding| available.contains(encoding)) {
+        let quality = qualities.iter()
+            .find(|(token, _)| token.eq_ignore_ascii_case(encoding.token()))
+            .map(|(_, quality)| *quality)
+            .unwrap_or(wildcard);
+        // Earlier codings win a tie.
+        if quality > 0.0 && best.map(|(_, best_quality)| quality > best_quality).unwrap_or(true) {
+            best = Some((*encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// # Add `Accept-Encoding` to `Vary`, as the response depends on it
+fn vary(headers: &mut HeaderMap) {
+    let varies = headers.get_all(header::VARY)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|name| name.trim() == "*" || name.trim().eq_ignore_ascii_case("accept-encoding"));
+    if !varies {
+        headers.append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+}
+
+/// # Where precompressed files are looked for
+#[derive(Clone, Debug)]
+pub(super) struct Precompressed {
+    pub distribution_dir: PathBuf,
+    pub uri_prefix: String,
+    /// Paths of the proxy rules, which are forwarded and never answered with a file.
+    pub proxy_paths: Vec<String>,
+}
+
+impl Precompressed {
+    /// # File of the request below the distribution directory, if it is a file
+    fn file(&self, req: &ServiceRequest) -> Option<PathBuf> {
+        let proxied = self.proxy_paths.iter().any(|proxy_path| {
+            req.path().strip_prefix(proxy_path.as_str()).map(|rest| rest.is_empty() || rest.starts_with('/')).unwrap_or(false)
+        });
+        if proxied {
+            return None;
+        }
+        let tail = match self.uri_prefix.trim_end_matches('/') {
+            "" => Some(req.path().trim_start_matches('/')),
+            uri_prefix => req.path().trim_start_matches('/').strip_prefix(uri_prefix).filter(|tail| tail.starts_with('/')),
+        }?;
+        let path = self.distribution_dir.join(upload::safe_path(tail)?);
+        match path.is_file() {
+            true => Some(path),
+            false => None,
+        }
+    }
+}
+
+/// # Path of the precompressed sibling, e.g. "main.js.br"
+fn sibling(path: &std::path::Path, encoding: Encoding) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(encoding.extension());
+    PathBuf::from(name)
+}
+
+/// # Middleware that compresses responses and delivers precompressed files
+/// Requests pass through unchanged without either.
+/// A precompressed sibling such as "main.js.br" is delivered for "main.js" with the content type of "main.js",
+/// as it is, so a precompressed page does not receive the live reload script.
+#[derive(Clone)]
+pub struct Compressor {
+    compression: Option<Arc<Compression>>,
+    precompressed: Option<Arc<Precompressed>>,
+}
+
+impl Compressor {
+    pub(super) fn new(compression: Option<Compression>, precompressed: Option<Precompressed>) -> Self {
+        Self{compression: compression.map(Arc::new), precompressed: precompressed.map(Arc::new)}
+    }
+}
+
+impl<S, B> Transform<S> for Compressor
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<CompressedBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CompressorMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CompressorMiddleware{service, compressor: self.clone()})
+    }
+}
+
+pub struct CompressorMiddleware<S> {
+    service: S,
+    compressor: Compressor,
+}
+
+impl<S, B> Service for CompressorMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<CompressedBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let accept_encoding = req.headers().get(header::ACCEPT_ENCODING)
+            .and_then(|accept_encoding| accept_encoding.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let get = req.method() == Method::GET || req.method() == Method::HEAD;
+        // Precompressed siblings of the requested file
+        let (file, siblings) = match (&self.compressor.precompressed, get) {
+            (Some(precompressed), true) => match precompressed.file(&req) {
+                Some(file) => {
+                    let siblings: Vec<Encoding> = ENCODINGS.iter().copied().filter(|encoding| sibling(&file, *encoding).is_file()).collect();
+                    (Some(file), siblings)
+                },
+                None => (None, Vec::new()),
+            },
+            _ => (None, Vec::new()),
+        };
+        if let (Some(file), Some(encoding)) = (&file, negotiate(&accept_encoding, &siblings)) {
+            // Otherwise the file itself is tried.
+            if let Ok(named_file) = File::open(sibling(file, encoding)).and_then(|opened| NamedFile::from_file(opened, file)) {
+                let (http_req, _) = req.into_parts();
+                let mut response = named_file.into_response(&http_req).unwrap_or_else(HttpResponse::from_error);
+                response.headers_mut().insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.token()));
+                vary(response.headers_mut());
+                return Box::pin(ok(ServiceResponse::new(http_req, response).map_body(|_, body| ResponseBody::Body(CompressedBody{body: body.into_body(), encoder: None}))));
+            }
+        }
+        let compression = self.compressor.compression.clone();
+        let response = self.service.call(req);
+        Box::pin(async move {
+            let mut response = response.await?;
+            if !siblings.is_empty() {
+                vary(response.headers_mut());
+            }
+            let compression = match compression {
+                Some(compression) => compression,
+                None => return Ok(response.map_body(|_, body| ResponseBody::Body(CompressedBody{body, encoder: None}))),
+            };
+            let compressible = response.headers().get(header::CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .map(|content_type| compression.compresses(content_type))
+                .unwrap_or(false);
+            let encoded = response.headers().contains_key(header::CONTENT_ENCODING);
+            if !compressible || encoded {
+                return Ok(response.map_body(|_, body| ResponseBody::Body(CompressedBody{body, encoder: None})));
+            }
+            vary(response.headers_mut());
+            let small = match response.response().body().size() {
+                BodySize::Sized(size) => size < compression.min_size,
+                BodySize::Stream => false,
+                BodySize::None | BodySize::Empty => true,
+            };
+            let encoding = match (response.status(), small) {
+                (StatusCode::OK, false) => negotiate(&accept_encoding, &ENCODINGS),
+                _ => None,
+            };
+            let (encoding, encoder) = match encoding.map(|encoding| (encoding, Encoder::new(encoding))) {
+                Some((encoding, Ok(encoder))) => (encoding, encoder),
+                _ => return Ok(response.map_body(|_, body| ResponseBody::Body(CompressedBody{body, encoder: None}))),
+            };
+            let headers = response.headers_mut();
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.token()));
+            headers.remove(header::CONTENT_LENGTH);
+            // The ranges of the file do not apply to the compressed body.
+            headers.remove(header::ACCEPT_RANGES);
+            // A strong validator would claim the bytes are those of the file.
+            let weak_etag = headers.get(header::ETAG)
+                .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+                .and_then(|etag| HeaderValue::from_bytes(&[b"W/", etag.as_bytes()].concat()).ok());
+            if let Some(weak_etag) = weak_etag {
+                headers.insert(header::ETAG, weak_etag);
+            }
+            Ok(response.map_body(move |_, body| ResponseBody::Body(CompressedBody{body, encoder: Some(encoder)})))
+        })
+    }
+}
+
+/// # Streaming encoder that writes into a buffer
+enum Encoder {
+    Brotli(brotli2::write::BrotliEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> io::Result<Self> {
+        // Fast levels, as every response is compressed while it is sent.
+        Ok(match encoding {
+            Encoding::Brotli => Self::Brotli(brotli2::write::BrotliEncoder::new(Vec::new(), 4)),
+            Encoding::Zstd => Self::Zstd(zstd::stream::write::Encoder::new(Vec::new(), 3)?),
+            Encoding::Gzip => Self::Gzip(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default())),
+        })
+    }
+    /// # Compress a chunk and take what is ready
+    /// Flushed every chunk, so that streamed responses such as server-sent events are not held back.
+    fn write(&mut self, chunk: &[u8]) -> io::Result<Bytes> {
+        let buffer = match self {
+            Self::Brotli(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            },
+            Self::Zstd(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            },
+            Self::Gzip(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            },
+        };
+        Ok(Bytes::from(std::mem::take(buffer)))
+    }
+    fn finish(self) -> io::Result<Bytes> {
+        let buffer = match self {
+            Self::Brotli(encoder) => encoder.finish()?,
+            Self::Zstd(encoder) => encoder.finish()?,
+            Self::Gzip(encoder) => encoder.finish()?,
+        };
+        Ok(Bytes::from(buffer))
+    }
+}
+
+/// # Body that is compressed while it is sent, or passed through without an encoder
+pub struct CompressedBody<B> {
+    body: ResponseBody<B>,
+    encoder: Option<Encoder>,
+}
+
+impl<B: MessageBody + Unpin> MessageBody for CompressedBody<B> {
+    fn size(&self) -> BodySize {
+        match self.encoder {
+            Some(_) => BodySize::Stream,
+            None => self.body.size(),
+        }
+    }
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Error>>> {
+        let this = self.get_mut();
+        loop {
+            let encoder = match &mut this.encoder {
+                Some(encoder) => encoder,
+                None => return Pin::new(&mut this.body).poll_next(cx),
+            };
+            match Pin::new(&mut this.body).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => match encoder.write(&chunk) {
+                    // The encoder may keep a small chunk to itself.
+                    Ok(compressed) if compressed.is_empty() => continue,
+                    Ok(compressed) => return Poll::Ready(Some(Ok(compressed))),
+                    Err(e) => return Poll::Ready(Some(Err(e.into()))),
+                },
+                Poll::Ready(None) => match this.encoder.take().map(Encoder::finish) {
+                    Some(Ok(compressed)) if !compressed.is_empty() => return Poll::Ready(Some(Ok(compressed))),
+                    Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                    _ => return Poll::Ready(None),
+                },
+                poll => return poll,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compress_responses() {
+        assert_eq!(negotiate("gzip, deflate, br", &ENCODINGS), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.5", &ENCODINGS), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0, *", &ENCODINGS), Some(Encoding::Zstd));
+        assert_eq!(negotiate("identity", &ENCODINGS), None);
+        assert_eq!(negotiate("br, zstd", &[Encoding::Gzip]), None);
+        assert_eq!(negotiate("x-gzip", &[Encoding::Gzip]), Some(Encoding::Gzip));
+        let compression = Compression{exclude: vec!["text/csv".to_string()], ..Compression::default()};
+        assert!(compression.compresses("text/html; charset=utf-8"));
+        assert!(compression.compresses("application/JSON"));
+        assert!(!compression.compresses("text/csv"));
+        assert!(!compression.compresses("text/event-stream"));
+        assert!(!Compression::default().compresses("text/event-stream; charset=utf-8"));
+        assert!(!compression.compresses("image/png"));
+        for encoding in ENCODINGS.iter() {
+            let mut encoder = Encoder::new(*encoding).unwrap();
+            let mut compressed = encoder.write(&b"hello ".repeat(100)).unwrap().to_vec();
+            compressed.extend_from_slice(&encoder.finish().unwrap());
+            assert!(!compressed.is_empty() && compressed.len() < 600);
+        }
+        let mut encoder = Encoder::new(Encoding::Gzip).unwrap();
+        let mut compressed = encoder.write(b"hello").unwrap().to_vec();
+        compressed.extend_from_slice(&encoder.finish().unwrap());
+        let mut decoded = String::new();
+        io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&compressed[..]), &mut decoded).unwrap();
+        assert_eq!(decoded, "hello");
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::archive::{self, ArchiveFormat};
+use super::protect::ProtectedFiles;
+use super::{listing, Spa};
+use super::super::Language;
+use actix_files::{Directory, NamedFile};
+use actix_web::{dev::ServiceResponse, http::header, HttpRequest, HttpResponse};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Index files tried by `--index` without a value.
+pub const DEFAULT_INDEX_FILES: [&str; 2] = ["index.html", "index.htm"];
+
+/// # How directories are delivered
+pub struct DirectorySettings {
+    /// File names tried in order when a directory is requested.
+    pub index_files: Vec<String>,
+    /// Show the files of a directory without an index file, otherwise 403.
+    pub listing: bool,
+    pub spa: Option<Arc<Spa>>,
+    /// The breadcrumbs of the listing start here.
+    pub uri_prefix: String,
+    /// Language of the listing and error pages.
+    pub language: Language,
+    /// Custom template of the listing, see `listing::LISTING_TEMPLATE`.
+    pub listing_template: Option<String>,
+    /// Show the upload form in the listing.
+    pub upload: bool,
+    /// Left out of the listing and the archives.
+    pub protected_files: ProtectedFiles,
+}
+
+/// # Entries of the listing, without the protected files
+fn visible_entries(settings: &DirectorySettings, dir: &Directory) -> io::Result<Vec<listing::Entry>> {
+    let mut entries = listing::walk(dir)?;
+    entries.retain(|entry| !settings.protected_files.contains(&dir.path.join(&entry.name)));
+    Ok(entries)
+}
+
+/// # Respond to a request for a directory
+/// The first index file that exists is delivered.
+/// Without one, the single-page application receives its index file,
+/// and otherwise the listing is shown or access is forbidden.
+/// Requests for the JSON listing skip the index files, `?download=zip` and `?download=tar.gz` return an archive.
+pub fn render(settings: &DirectorySettings, dir: &Directory, req: &HttpRequest) -> io::Result<ServiceResponse> {
+    // An archive shows the contents like the listing does, so it is forbidden along with it.
+    if let Some(format) = ArchiveFormat::from_query(req.query_string()) {
+        let response = match settings.listing {
+            true => archive::response(&dir.path, format, settings.protected_files.clone()),
+            false => HttpResponse::Forbidden().content_type("text/html; charset=utf-8").body(listing::forbidden_html(settings.language)),
+        };
+        return Ok(ServiceResponse::new(req.clone(), response));
+    }
+    let wants_json = wants_json(req);
+    let spa_index = settings.spa.as_ref().map(|spa| spa.index_file.to_string_lossy().into_owned());
+    // In SPA mode, a directory is looked up with the index file name of the application unless told otherwise.
+    let index_files = match (wants_json, settings.index_files.is_empty(), &spa_index) {
+        (true, _, _) => &[],
+        (false, true, Some(spa_index)) => std::slice::from_ref(spa_index),
+        (false, _, _) => settings.index_files.as_slice(),
+    };
+    if let Some(index_file) = index_files.iter().map(|index_file| dir.path.join(index_file)).find(|path| path.is_file()) {
+        // Relative links in the index file need the trailing slash.
+        if !req.path().ends_with('/') {
+            let location = match req.query_string() {
+                "" => format!("{}/", req.path()),
+                query => format!("{}/?{}", req.path(), query),
+            };
+            return Ok(ServiceResponse::new(req.clone(), HttpResponse::Found().header(header::LOCATION, location).finish()));
+        }
+        return named_file(&index_file, req);
+    }
+    if let (Some(spa), false) = (&settings.spa, wants_json) {
+        return named_file(&dir.base.join(&spa.index_file), req);
+    }
+    let response = match (settings.listing, wants_json) {
+        (true, false) => {
+            let entries = visible_entries(settings, dir)?;
+            HttpResponse::Ok().content_type("text/html; charset=utf-8")
+                .body(listing::html(&entries, req.path(), &settings.uri_prefix, settings.language, settings.listing_template.as_deref(), settings.upload))
+        },
+        (true, true) => {
+            let entries = visible_entries(settings, dir)?;
+            let connection_info = req.connection_info();
+            let base_url = format!("{}://{}", connection_info.scheme(), connection_info.host());
+            HttpResponse::Ok().content_type("application/json").body(listing::json(&entries, req.path(), &base_url))
+        },
+        (false, false) => HttpResponse::Forbidden().content_type("text/html; charset=utf-8").body(listing::forbidden_html(settings.language)),
+        (false, true) => HttpResponse::Forbidden().content_type("application/json").body(r#"{"error":"directory listing is disabled"}"#),
+    };
+    Ok(ServiceResponse::new(req.clone(), response))
+}
+
+/// # Whether the JSON listing is requested
+/// Either with `?format=json` or with `Accept: application/json`.
+fn wants_json(req: &HttpRequest) -> bool {
+    let format_json = req.query_string().split('&').any(|pair| pair == "format=json");
+    let accept_json = req.headers().get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains("application/json"))
+        .unwrap_or(false);
+    format_json || accept_json
+}
+
+/// # Deliver a file in place of the directory
+fn named_file(path: &Path, req: &HttpRequest) -> io::Result<ServiceResponse> {
+    let response = NamedFile::open(path)?.into_response(req)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(ServiceResponse::new(req.clone(), response))
+}
@@ -0,0 +1,55 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// # Something that happened while the server is running
+/// Passed to the `HappyServerBuilder::on_event` handler, and printed by `HappyServerViewer::output_event`.
+#[derive(Clone, Debug)]
+pub enum ServerEvent {
+    /// A file was uploaded.
+    Uploaded {
+        /// Path of the saved file, relative to the distribution directory.
+        path: PathBuf,
+        size: u64,
+        /// A file with the same name was replaced.
+        replaced: bool,
+        client: Option<String>,
+    },
+    /// An upload was refused, e.g. because it is too large or the file exists.
+    UploadRejected {
+        /// Requested path, relative to the distribution directory.
+        path: PathBuf,
+        reason: UploadRejection,
+        client: Option<String>,
+    },
+    /// A request was refused because of the address of the client, see `IpFilter`.
+    AccessDenied {
+        /// Requested path.
+        path: String,
+        client: IpAddr,
+    },
+    /// A line of the access log, when it is not written to a file, see `AccessLog`.
+    Access {
+        line: String,
+    },
+}
+
+/// # Why an upload was refused
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UploadRejection {
+    /// Larger than `Upload::max_size`, or a form post larger than `Upload::max_request_size`.
+    TooLarge,
+    /// The file exists and the overwrite policy is `Overwrite::Reject`.
+    Exists,
+    /// The path is hidden, leaves the distribution directory, or is a directory.
+    InvalidPath,
+    /// The file could not be written.
+    Io,
+}
+
+/// # Receives the events of a running server
+/// Called on the worker threads, so it should return quickly, e.g. by sending the event to a channel.
+pub type EventHandler = Arc<dyn Fn(ServerEvent) + Send + Sync>;
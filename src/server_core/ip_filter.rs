@@ -0,0 +1,180 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::event::{EventHandler, ServerEvent};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, Either, Ready};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Ranges allowed by `--lan_only`: loopback, the private ranges of RFC 1918,
+/// IPv4 link-local, IPv6 unique local and link-local addresses.
+pub const LAN_RANGES: [&str; 8] = [
+    "127.0.0.0/8", "10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "169.254.0.0/16",
+    "::1/128", "fc00::/7", "fe80::/10",
+];
+
+/// # A range of addresses in CIDR notation, e.g. "192.168.0.0/16" or "fe80::/10"
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpRange {
+    pub addr: IpAddr,
+    /// Number of leading bits that have to match.
+    pub prefix_len: u8,
+}
+
+impl IpRange {
+    /// # Parse a range in CIDR notation
+    /// A single address without "/" is a range of one address.
+    pub fn parse(range: &str) -> Option<Self> {
+        let (addr, prefix_len) = match range.trim().split_once('/') {
+            Some((addr, prefix_len)) => (addr.parse::<IpAddr>().ok()?, Some(prefix_len.parse::<u8>().ok()?)),
+            None => (range.trim().parse::<IpAddr>().ok()?, None),
+        };
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        match prefix_len.unwrap_or(max_len) {
+            prefix_len if prefix_len <= max_len => Some(Self{addr, prefix_len}),
+            _ => None,
+        }
+    }
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Dual-stack listeners see IPv4 clients as "::ffff:a.b.c.d".
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => matches_prefix(u32::from(range) as u128, u32::from(ip) as u128, 32, self.prefix_len),
+            (IpAddr::V6(range), IpAddr::V6(ip)) => matches_prefix(u128::from(range), u128::from(ip), 128, self.prefix_len),
+            _ => false,
+        }
+    }
+}
+
+fn matches_prefix(range: u128, ip: u128, bits: u8, prefix_len: u8) -> bool {
+    match prefix_len {
+        0 => true,
+        prefix_len => (range ^ ip) >> (bits - prefix_len) == 0,
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// # Clients allowed to access the server, by address
+/// A client in a denied range is refused. Otherwise, if allowed ranges are given, the client has to be in one of them.
+/// Refused requests are answered with 403 Forbidden before anything else, and reported as `ServerEvent::AccessDenied`.
+/// Clients connected through a Unix domain socket are always allowed.
+#[derive(Clone, Debug, Default)]
+pub struct IpFilter {
+    pub allow: Vec<IpRange>,
+    pub deny: Vec<IpRange>,
+}
+
+impl IpFilter {
+    /// # Allow only the local network, see `LAN_RANGES`
+    pub fn lan_only() -> Self {
+        Self{allow: LAN_RANGES.iter().filter_map(|range| IpRange::parse(range)).collect(), deny: Vec::new()}
+    }
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|range| range.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|range| range.contains(ip)))
+    }
+}
+
+/// # Middleware that refuses clients by address
+/// Requests pass through unchanged without `IpFilter`.
+/// The address of the connection is used, not `X-Forwarded-For`, which clients can forge.
+#[derive(Clone)]
+pub struct AccessControl {
+    ip_filter: Option<Arc<IpFilter>>,
+    on_event: Option<EventHandler>,
+}
+
+impl AccessControl {
+    pub fn new(ip_filter: Option<IpFilter>, on_event: Option<EventHandler>) -> Self {
+        Self{ip_filter: ip_filter.map(Arc::new), on_event}
+    }
+}
+
+impl<S, B> Transform<S> for AccessControl
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AccessControlMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AccessControlMiddleware{service, access_control: self.clone()})
+    }
+}
+
+pub struct AccessControlMiddleware<S> {
+    service: S,
+    access_control: AccessControl,
+}
+
+impl<S, B> Service for AccessControlMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let client = match (&self.access_control.ip_filter, req.peer_addr()) {
+            (Some(ip_filter), Some(peer_addr)) if !ip_filter.allows(peer_addr.ip()) => peer_addr.ip(),
+            _ => return Either::Left(self.service.call(req)),
+        };
+        if let Some(on_event) = &self.access_control.on_event {
+            on_event(ServerEvent::AccessDenied{path: req.path().to_string(), client});
+        }
+        let response = HttpResponse::Forbidden().content_type("text/plain; charset=utf-8").body("403 Forbidden");
+        Either::Right(ok(req.into_response(response).map_body(|_, body| body.into_body())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ip_ranges() {
+        let range = IpRange::parse("192.168.0.0/16").unwrap();
+        assert!(range.contains("192.168.10.1".parse().unwrap()));
+        assert!(range.contains("::ffff:192.168.10.1".parse().unwrap()));
+        assert!(!range.contains("192.169.0.1".parse().unwrap()));
+        assert!(IpRange::parse("0.0.0.0/0").unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!(IpRange::parse("2001:db8::1").unwrap().contains("2001:db8::1".parse().unwrap()));
+        assert!(IpRange::parse("10.0.0.0/33").is_none());
+        assert!(IpRange::parse("example.com/8").is_none());
+        let lan_only = IpFilter::lan_only();
+        assert!(lan_only.allows("172.31.255.255".parse().unwrap()));
+        assert!(lan_only.allows("fe80::1".parse().unwrap()));
+        assert!(!lan_only.allows("172.32.0.1".parse().unwrap()));
+        let ip_filter = IpFilter{deny: vec![IpRange::parse("10.0.0.5").unwrap()], ..lan_only};
+        assert!(!ip_filter.allows("10.0.0.5".parse().unwrap()));
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::super::model::{DEFAULT_HTTP_PORT, DEFAULT_HTTPS_PORT};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// # Where a listener accepts connections
+#[derive(Clone, Debug)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// Unix domain socket, only plain http.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// # URL to reach the server through a network interface
+#[derive(Clone, Debug)]
+pub struct InterfaceUrl {
+    /// Name of the interface, e.g. "eth0" or "Wi-Fi".
+    pub interface: String,
+    pub ip: IpAddr,
+    pub url: String,
+}
+
+/// # Addresses of the non-loopback network interfaces
+/// Returns an empty list if the interfaces cannot be listed.
+pub fn interface_addrs() -> Vec<(String, IpAddr)> {
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces.into_iter()
+            .filter(|interface| !interface.is_loopback())
+            .map(|interface| {
+                let ip = interface.ip();
+                (interface.name, ip)
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// # One address the server listens on
+#[derive(Clone, Debug)]
+pub struct Listener {
+    pub addr: ListenAddr,
+    /// Deliver via https on this listener, with the certificate of `HappyServerBuilder::ssl`.
+    pub tls: bool,
+}
+
+impl Listener {
+    /// # Plain http listener
+    pub fn http(socket_addr: SocketAddr) -> Self {
+        Self{addr: ListenAddr::Tcp(socket_addr), tls: false}
+    }
+    /// # https listener
+    pub fn https(socket_addr: SocketAddr) -> Self {
+        Self{addr: ListenAddr::Tcp(socket_addr), tls: true}
+    }
+    /// # Plain http listener on a unix domain socket
+    #[cfg(unix)]
+    pub fn unix(path: impl Into<PathBuf>) -> Self {
+        Self{addr: ListenAddr::Unix(path.into()), tls: false}
+    }
+    pub fn scheme(&self) -> &'static str {
+        if self.tls {"https"} else {"http"}
+    }
+    /// # URL to browse the delivered directory through this listener
+    /// The port is omitted if it is the default port of the scheme.
+    /// When listening on all interfaces, the url points to localhost.
+    /// Unix sockets are shown in the `http+unix://` form with the percent-encoded socket path.
+    pub fn url(&self, uri_prefix: &str) -> String {
+        match &self.addr {
+            ListenAddr::Tcp(socket_addr) => match socket_addr.ip() {
+                ip if ip.is_unspecified() => self.tcp_url("localhost", socket_addr.port(), uri_prefix),
+                ip => self.tcp_url(&host(ip), socket_addr.port(), uri_prefix),
+            },
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => format!("http+unix://{}/{}", path.display().to_string().replace('/', "%2F"), uri_prefix),
+        }
+    }
+    /// # URLs for the other devices on the network
+    /// Only a listener on all interfaces (0.0.0.0 or ::) is reachable through each interface.
+    /// `::` also covers IPv4 (dual-stack). IPv6 link-local addresses are left out, browsers cannot open them.
+    pub fn interface_urls(&self, uri_prefix: &str) -> Vec<InterfaceUrl> {
+        let socket_addr = match self.addr {
+            ListenAddr::Tcp(socket_addr) if socket_addr.ip().is_unspecified() => socket_addr,
+            _ => return Vec::new(),
+        };
+        interface_addrs().into_iter()
+            .filter(|(_, ip)| match (socket_addr.ip(), ip) {
+                (IpAddr::V4(_), IpAddr::V4(_)) => true,
+                (IpAddr::V6(_), IpAddr::V4(_)) => true,
+                (IpAddr::V6(_), IpAddr::V6(ip)) => ip.segments()[0] & 0xffc0 != 0xfe80,
+                (IpAddr::V4(_), IpAddr::V6(_)) => false,
+            })
+            .map(|(interface, ip)| InterfaceUrl{
+                interface,
+                ip,
+                url: self.tcp_url(&host(ip), socket_addr.port(), uri_prefix),
+            })
+            .collect()
+    }
+    fn tcp_url(&self, host: &str, port: u16, uri_prefix: &str) -> String {
+        match (self.scheme(), port) {
+            ("http", DEFAULT_HTTP_PORT) | ("https", DEFAULT_HTTPS_PORT) => format!("{}://{}/{}", self.scheme(), host, uri_prefix),
+            (scheme, port) => format!("{}://{}:{}/{}", scheme, host, port, uri_prefix),
+        }
+    }
+}
+
+/// # Host part of a url
+fn host(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    }
+}
+
+/// Number of ports tried by auto port before giving up.
+const AUTO_PORT_ATTEMPTS: u16 = 100;
+
+/// # Open the listening socket
+/// With auto port, the following ports are tried one by one while the port is already in use.
+/// Port 0 lets the OS choose a free port.
+pub fn listen(socket_addr: SocketAddr, auto_port: bool) -> io::Result<TcpListener> {
+    let mut socket_addr = socket_addr;
+    let mut attempts = 1;
+    loop {
+        match listen_socket(socket_addr) {
+            Err(e) if auto_port && e.kind() == io::ErrorKind::AddrInUse
+                && socket_addr.port() != 0 && socket_addr.port() < u16::MAX && attempts < AUTO_PORT_ATTEMPTS => {
+                socket_addr.set_port(socket_addr.port() + 1);
+                attempts += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+/// # Open one listening socket
+/// The socket is created by hand so that `::` can accept IPv4 connections as well (dual-stack),
+/// which is not the default on every OS.
+fn listen_socket(socket_addr: SocketAddr) -> io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::for_address(socket_addr), Type::STREAM, Some(Protocol::TCP))?;
+    if let SocketAddr::V6(addr) = socket_addr {
+        socket.set_only_v6(!addr.ip().is_unspecified())?;
+    }
+    // Same as actix-web: allow restarting on the port right away, except on Windows where it allows port hijacking.
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.bind(&socket_addr.into())?;
+    socket.listen(2048)?;
+    Ok(socket.into())
+}
+
+/// # Remove a socket file left behind by a previous run
+/// Anything other than a socket is left alone, so that binding fails instead of deleting a user's file.
+#[cfg(unix)]
+pub fn remove_stale_socket(path: &std::path::Path) {
+    use std::os::unix::fs::FileTypeExt;
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::super::Language;
+use actix_files::Directory;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::fmt::Write;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use v_htmlescape::escape;
+
+/// Built-in template of the listing page.
+/// A custom template can use the same placeholders: `{{lang}}`, `{{title}}`, `{{breadcrumbs}}`, `{{downloads}}`, `{{upload}}` and `{{table}}`.
+/// `{{upload}}` is the upload form, empty unless uploads are accepted.
+pub const LISTING_TEMPLATE: &str = include_str!("listing.html");
+
+/// Characters escaped in a path segment of a link.
+const SEGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'/').add(b'<').add(b'>').add(b'?').add(b'`').add(b'{').add(b'}');
+
+/// # A file or directory shown in a listing
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    /// Size in bytes, 0 for directories.
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// Guessed from the extension, None for directories.
+    pub mime: Option<String>,
+}
+
+impl Entry {
+    /// # Link to the entry from a listing of `dir_url`
+    /// `dir_url` is the percent-encoded path of the directory, ending with "/".
+    pub fn url(&self, dir_url: &str) -> String {
+        let slash = if self.is_dir {"/"} else {""};
+        format!("{}{}{}", dir_url, encode_segment(&self.name), slash)
+    }
+}
+
+/// # Percent-encode a file name for a link
+pub fn encode_segment(name: &str) -> String {
+    utf8_percent_encode(name, SEGMENT).to_string()
+}
+
+/// # Read the entries of a directory
+/// Hidden files (starting with ".") are left out. Directories come first, then files, each by name.
+/// Symbolic links are followed for the size and the kind of entry.
+pub fn walk(dir: &Directory) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in dir.path.read_dir()? {
+        if !dir.is_visible(&entry) {
+            continue;
+        }
+        let entry = entry?;
+        let metadata = match std::fs::metadata(entry.path()).or_else(|_| entry.metadata()) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_dir = metadata.is_dir();
+        entries.push(Entry {
+            mime: if is_dir {None} else {Some(mime_guess::from_path(&name).first_or_octet_stream().to_string())},
+            size: if is_dir {0} else {metadata.len()},
+            modified: metadata.modified().ok(),
+            name,
+            is_dir,
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase())));
+    Ok(entries)
+}
+
+/// # Percent-encoded path of the directory, ending with "/"
+pub fn dir_url(request_path: &str) -> String {
+    match request_path.ends_with('/') {
+        true => request_path.to_string(),
+        false => format!("{}/", request_path),
+    }
+}
+
+/// # Render the listing page
+/// The breadcrumbs start at the uri prefix, the directories above it are not delivered.
+/// The upload form is added if `upload` is true.
+pub fn html(entries: &[Entry], request_path: &str, uri_prefix: &str, language: Language, template: Option<&str>, upload: bool) -> String {
+    let dir_url = dir_url(request_path);
+    let root_url = match uri_prefix.trim_end_matches('/') {
+        "" => "/".to_string(),
+        uri_prefix => format!("/{}/", uri_prefix),
+    };
+    let display_path = percent_decode_str(&dir_url).decode_utf8_lossy().into_owned();
+    let title = match language {
+        Language::Japanese => format!("{} の一覧", display_path),
+        Language::English => format!("Index of {}", display_path),
+    };
+
+    // breadcrumbs
+    let root_label = match uri_prefix.trim_end_matches('/') {
+        "" => "/",
+        uri_prefix => uri_prefix,
+    };
+    // The urls come from the request path, they are escaped like the names.
+    let mut breadcrumbs = format!("<a href=\"{}\">{}</a>", escape(&root_url), escape(root_label));
+    let mut crumb_url = root_url.clone();
+    for segment in dir_url.strip_prefix(&root_url).unwrap_or_default().split('/').filter(|segment| !segment.is_empty()) {
+        crumb_url = format!("{}{}/", crumb_url, segment);
+        let _ = write!(breadcrumbs, "<span>/</span><a href=\"{}\">{}</a>", escape(&crumb_url), escape(&percent_decode_str(segment).decode_utf8_lossy()));
+    }
+
+    // archive downloads
+    let downloads = format!("{download}: <a href=\"{dir_url}?download=zip\">zip</a> <a href=\"{dir_url}?download=tar.gz\">tar.gz</a>",
+        download = match language {Language::Japanese => "ダウンロード", Language::English => "Download"},
+        dir_url = escape(&dir_url));
+
+    // upload form, posted to this directory
+    let upload = match upload {
+        true => format!("<form class=\"upload\" method=\"post\" enctype=\"multipart/form-data\" action=\"{}\">\
+            <input type=\"file\" name=\"file\" multiple required> <button type=\"submit\">{}</button></form>",
+            escape(&dir_url), match language {Language::Japanese => "アップロード", Language::English => "Upload"}),
+        false => String::new(),
+    };
+
+    // table
+    let (name, size, modified, empty) = match language {
+        Language::Japanese => ("名前", "サイズ", "更新日時", "このディレクトリは空です"),
+        Language::English => ("Name", "Size", "Modified", "This directory is empty"),
+    };
+    let mut table = format!("<table><thead><tr><th data-key=\"name\">{}</th><th class=\"size\" data-key=\"size\">{}</th><th data-key=\"mtime\">{}</th></tr></thead><tbody>",
+        name, size, modified);
+    for entry in entries {
+        let mtime = entry.modified.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok()).map(|duration| duration.as_secs());
+        let _ = write!(table, "<tr data-name=\"{name}\" data-dir=\"{dir}\" data-size=\"{size}\" data-mtime=\"{mtime}\">\
+            <td class=\"name\"><span class=\"icon\" title=\"{mime}\">{icon}</span><a href=\"{url}\">{name}{slash}</a></td>\
+            <td class=\"size\">{human_size}</td><td>{modified}</td></tr>",
+            name = escape(&entry.name),
+            dir = entry.is_dir as u8,
+            size = entry.size,
+            mtime = mtime.unwrap_or_default(),
+            mime = escape(entry.mime.as_deref().unwrap_or("directory")),
+            icon = icon(entry),
+            url = escape(&entry.url(&dir_url)),
+            slash = if entry.is_dir {"/"} else {""},
+            human_size = if entry.is_dir {"-".to_string()} else {human_size(entry.size)},
+            modified = mtime.map(format_time).unwrap_or_default(),
+        );
+    }
+    if entries.is_empty() {
+        let _ = write!(table, "<tr><td class=\"empty\" colspan=\"3\">{}</td></tr>", empty);
+    }
+    table.push_str("</tbody></table>");
+
+    fill(template.unwrap_or(LISTING_TEMPLATE), &[
+        ("lang", match language {Language::Japanese => "ja", Language::English => "en"}),
+        ("title", &escape(&title).to_string()),
+        ("breadcrumbs", &breadcrumbs),
+        ("downloads", &downloads),
+        ("upload", &upload),
+        ("table", &table),
+    ])
+}
+
+/// # Replace the placeholders such as `{{title}}` in one pass over the template
+/// Values are not searched again, so a file named like a placeholder stays as it is.
+/// Unknown placeholders are kept.
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        let placeholder = &rest[start + 2..];
+        match values.iter().find(|(name, _)| placeholder.strip_prefix(name).map(|end| end.starts_with("}}")).unwrap_or(false)) {
+            Some((name, value)) => {
+                filled.push_str(value);
+                rest = &placeholder[name.len() + 2..];
+            },
+            None => {
+                filled.push_str("{{");
+                rest = placeholder;
+            },
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// # Entry of the JSON listing
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    name: &'a str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    size: u64,
+    /// RFC 3339 in UTC.
+    mtime: Option<String>,
+    mime: Option<&'a str>,
+    url: String,
+}
+
+/// # JSON listing
+#[derive(Serialize)]
+struct JsonListing<'a> {
+    /// Decoded path of the directory.
+    path: String,
+    entries: Vec<JsonEntry<'a>>,
+}
+
+/// # JSON listing of the directory
+/// `base_url` is prepended to the urls of the entries, e.g. "http://localhost:8080".
+pub fn json(entries: &[Entry], request_path: &str, base_url: &str) -> String {
+    let dir_url = dir_url(request_path);
+    let entries: Vec<JsonEntry> = entries.iter().map(|entry| JsonEntry {
+        name: &entry.name,
+        kind: if entry.is_dir {"directory"} else {"file"},
+        size: entry.size,
+        mtime: entry.modified.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok()).map(|duration| format_rfc3339(duration.as_secs())),
+        mime: entry.mime.as_deref(),
+        url: format!("{}{}", base_url, entry.url(&dir_url)),
+    }).collect();
+    let listing = JsonListing{path: percent_decode_str(&dir_url).decode_utf8_lossy().into_owned(), entries};
+    serde_json::to_string(&listing).unwrap_or_default()
+}
+
+/// # Page for a directory without an index file when the listing is disabled
+pub fn forbidden_html(language: Language) -> String {
+    let (lang, message) = match language {
+        Language::Japanese => ("ja", "このディレクトリの一覧は表示できません。"),
+        Language::English => ("en", "Directory listing is disabled."),
+    };
+    format!("<!DOCTYPE html>\n<html lang=\"{}\"><head><meta charset=\"utf-8\"><title>403 Forbidden</title></head>\
+        <body><h1>403 Forbidden</h1><p>{}</p></body>\n</html>", lang, message)
+}
+
+/// # Icon for the kind of file
+fn icon(entry: &Entry) -> &'static str {
+    let mime = match &entry.mime {
+        Some(mime) => mime.as_str(),
+        None => return "📁",
+    };
+    match mime.split('/').next().unwrap_or_default() {
+        "image" => "🖼️",
+        "video" => "🎞️",
+        "audio" => "🎵",
+        "font" => "🔤",
+        "text" => "📝",
+        _ => match mime {
+            "application/pdf" => "📕",
+            "application/zip" | "application/gzip" | "application/x-tar" | "application/x-7z-compressed"
+                | "application/vnd.rar" | "application/x-bzip2" | "application/x-xz" => "📦",
+            "application/json" | "application/javascript" | "application/xml" | "application/wasm" => "📝",
+            _ => "📄",
+        },
+    }
+}
+
+/// # Size in binary units, e.g. "1.5 KiB"
+pub fn human_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// # UTC date and time, e.g. "2021-07-10 12:34"
+pub fn format_time(unix_time: u64) -> String {
+    let (year, month, day, seconds) = civil_time(unix_time);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, seconds / 3600, seconds % 3600 / 60)
+}
+
+/// # UTC date and time in RFC 3339, e.g. "2021-07-10T12:34:56Z"
+pub fn format_rfc3339(unix_time: u64) -> String {
+    let (year, month, day, seconds) = civil_time(unix_time);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, seconds / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+/// # Year, month, day and seconds of the day
+/// Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+pub fn civil_time(unix_time: u64) -> (i64, i64, i64, u64) {
+    let (days, seconds) = ((unix_time / 86400) as i64, unix_time % 86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+    (year, month, day, seconds)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn listing_formats() {
+        assert_eq!(format_time(0), "1970-01-01 00:00");
+        assert_eq!(format_time(1625920440), "2021-07-10 12:34");
+        assert_eq!(format_time(951782400), "2000-02-29 00:00");
+        assert_eq!(format_rfc3339(1625920496), "2021-07-10T12:34:56Z");
+        assert_eq!(human_size(1000), "1000 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        let entry = Entry{name: "a b#.txt".to_string(), is_dir: false, size: 0, modified: None, mime: None};
+        assert_eq!(entry.url("/app/"), "/app/a%20b%23.txt");
+        let page = html(&[entry], "/app/x\"><script>/", "app", Language::English, None, true);
+        assert!(!page.contains("x\"><script>") && page.contains("x&quot;&gt;&lt;script&gt;"));
+        // names that look like placeholders are not filled in
+        let page = html(&[], "/{{table}}/{{upload}}/", "", Language::English, None, false);
+        assert_eq!(page.matches("<table").count(), 1);
+        assert!(page.contains("{{upload}}") && !page.contains("<form"));
+        assert_eq!(fill("{{a}}-{{b}}-{{c}}", &[("a", "{{b}}"), ("b", "x")]), "{{b}}-x-{{c}}");
+    }
+}
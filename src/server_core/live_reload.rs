@@ -0,0 +1,290 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::listing;
+use actix_web::body::{Body, MessageBody, ResponseBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{http::header, http::Method, http::StatusCode, web, web::Bytes, Error, HttpResponse, Resource};
+use futures::channel::mpsc;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use notify::{RecursiveMode, Watcher};
+use std::io;
+use std::path::{Component, Path};
+use std::pin::Pin;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// Path of the Server-Sent Events endpoint below the uri prefix. Hidden, so that it never hides a file.
+const ENDPOINT: &str = ".happy_server/livereload";
+/// Changes closer together than this are reported once, e.g. a build writing many files.
+const DEBOUNCE: Duration = Duration::from_millis(150);
+/// Browsers are pinged this often while nothing changes, so that closed connections are noticed and forgotten.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// # What the browsers are told to do
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Change {
+    /// Only stylesheets changed, they are swapped without reloading, with their URL paths.
+    Css(Vec<String>),
+    Reload,
+}
+
+/// # Browsers waiting for changes
+pub struct LiveReload {
+    endpoint: String,
+    clients: Mutex<Vec<mpsc::UnboundedSender<Result<Bytes, io::Error>>>>,
+    /// Kept so that the directory stays watched while the server runs.
+    watcher: Mutex<Option<notify::RecommendedWatcher>>,
+}
+
+impl LiveReload {
+    /// # Watch the distribution directory recursively
+    pub fn watch(distribution_dir: &Path, uri_prefix: &str) -> notify::Result<Arc<Self>> {
+        let prefix = match uri_prefix.trim_end_matches('/') {
+            "" => String::new(),
+            uri_prefix => format!("/{}", uri_prefix),
+        };
+        let live_reload = Arc::new(Self{endpoint: format!("{}/{}", prefix, ENDPOINT), clients: Mutex::new(Vec::new()), watcher: Mutex::new(None)});
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                if !event.kind.is_access() {
+                    let _ = sender.send(event.paths);
+                }
+            }
+        })?;
+        // Watched by its absolute path, so that the events carry absolute paths on every platform.
+        let base = distribution_dir.canonicalize().unwrap_or_else(|_| distribution_dir.to_path_buf());
+        watcher.watch(&base, RecursiveMode::Recursive)?;
+        if let Ok(mut kept) = live_reload.watcher.lock() {
+            *kept = Some(watcher);
+        }
+        // The events are collected on a thread of their own, which ends with the server.
+        let weak = Arc::downgrade(&live_reload);
+        std::thread::spawn(move || loop {
+            let mut paths = match receiver.recv_timeout(PING_INTERVAL) {
+                Ok(paths) => paths,
+                Err(RecvTimeoutError::Timeout) => match weak.upgrade() {
+                    Some(live_reload) => {
+                        live_reload.broadcast(": ping\n\n");
+                        continue;
+                    },
+                    None => break,
+                },
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            while let Ok(more) = receiver.recv_timeout(DEBOUNCE) {
+                paths.extend(more);
+            }
+            // A file written in several steps is reported once.
+            let mut url_paths: Vec<String> = paths.iter().filter_map(|path| url_path(&base, path, &prefix)).collect();
+            url_paths.sort();
+            url_paths.dedup();
+            let change = match url_paths.iter().all(|url_path| url_path.to_lowercase().ends_with(".css")) {
+                _ if url_paths.is_empty() => continue,
+                true => Change::Css(url_paths),
+                false => Change::Reload,
+            };
+            match weak.upgrade() {
+                Some(live_reload) => live_reload.notify(&change),
+                None => break,
+            }
+        });
+        Ok(live_reload)
+    }
+    /// # Tell the connected browsers
+    fn notify(&self, change: &Change) {
+        let message = match change {
+            Change::Css(url_paths) => format!("event: css\ndata: {}\n\n", serde_json::to_string(url_paths).unwrap_or_default()),
+            Change::Reload => "event: reload\ndata: \n\n".to_string(),
+        };
+        self.broadcast(&message);
+    }
+    /// # Send a message to every browser, forgetting the ones that left
+    /// The stream of a closed connection is dropped once a message fails to reach it, so the next one finds it gone.
+    fn broadcast(&self, message: &str) {
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.retain(|client| client.unbounded_send(Ok(Bytes::from(message.to_string()))).is_ok());
+        }
+    }
+    /// # Script that connects to the endpoint
+    fn script(&self) -> String {
+        format!(r#"<script>
+(function () {{
+  var source = new EventSource("{endpoint}");
+  source.addEventListener("reload", function () {{ location.reload(); }});
+  source.addEventListener("css", function (event) {{
+    var changed = JSON.parse(event.data).map(decodeURI);
+    document.querySelectorAll('link[rel="stylesheet"]').forEach(function (link) {{
+      var url = new URL(link.href);
+      if (url.origin !== location.origin || changed.indexOf(decodeURI(url.pathname)) < 0) {{ return; }}
+      url.searchParams.set("livereload", Date.now());
+      link.href = url.href;
+    }});
+  }});
+}})();
+</script>
+"#, endpoint=self.endpoint)
+    }
+}
+
+/// # URL path of a changed file, None for hidden and backup files
+fn url_path(base: &Path, path: &Path, prefix: &str) -> Option<String> {
+    let relative = path.strip_prefix(base).ok()?;
+    let mut segments = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(segment) => {
+                let segment = segment.to_string_lossy();
+                if segment.starts_with('.') || segment.ends_with('~') {
+                    return None;
+                }
+                segments.push(listing::encode_segment(&segment));
+            },
+            _ => return None,
+        }
+    }
+    match segments.is_empty() {
+        true => None,
+        false => Some(format!("{}/{}", prefix, segments.join("/"))),
+    }
+}
+
+/// # Route of the Server-Sent Events endpoint
+pub fn resource(live_reload: Arc<LiveReload>) -> Resource {
+    web::resource(live_reload.endpoint.clone()).route(web::get().to(move || {
+        let (sender, receiver) = mpsc::unbounded();
+        // A comment, so that the browser sees the connection open.
+        let _ = sender.unbounded_send(Ok(Bytes::from_static(b": connected\n\n")));
+        if let Ok(mut clients) = live_reload.clients.lock() {
+            clients.push(sender);
+        }
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .streaming(receiver)
+    }))
+}
+
+/// # Insert the script before `</body>`, or at the end
+fn inject(html: &[u8], script: &str) -> Bytes {
+    let position = html.windows(7).rposition(|window| window.eq_ignore_ascii_case(b"</body>")).unwrap_or(html.len());
+    let mut injected = Vec::with_capacity(html.len() + script.len());
+    injected.extend_from_slice(&html[..position]);
+    injected.extend_from_slice(script.as_bytes());
+    injected.extend_from_slice(&html[position..]);
+    Bytes::from(injected)
+}
+
+/// # Middleware that adds the script to HTML pages
+/// Requests pass through unchanged without `LiveReload`.
+#[derive(Clone)]
+pub struct ScriptInjector {
+    live_reload: Option<Arc<LiveReload>>,
+}
+
+impl ScriptInjector {
+    pub fn new(live_reload: Option<Arc<LiveReload>>) -> Self {
+        Self{live_reload}
+    }
+}
+
+impl<S, B> Transform<S> for ScriptInjector
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ScriptInjectorMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ScriptInjectorMiddleware{service, script_injector: self.clone()})
+    }
+}
+
+pub struct ScriptInjectorMiddleware<S> {
+    service: S,
+    script_injector: ScriptInjector,
+}
+
+impl<S, B> Service for ScriptInjectorMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let script = match (&self.script_injector.live_reload, req.method() == Method::GET) {
+            (Some(live_reload), true) => live_reload.script(),
+            _ => return Box::pin(self.service.call(req)),
+        };
+        let response = self.service.call(req);
+        Box::pin(async move {
+            let mut response = response.await?;
+            let html = response.headers().get(header::CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .map(|content_type| content_type.starts_with("text/html"))
+                .unwrap_or(false);
+            // Partial and unmodified responses are left alone, the script is in the full page.
+            if !html || response.status() != StatusCode::OK {
+                return Ok(response);
+            }
+            let mut body = response.take_body();
+            let mut html = Vec::new();
+            while let Some(chunk) = futures::future::poll_fn(|cx| Pin::new(&mut body).poll_next(cx)).await {
+                html.extend_from_slice(&chunk?);
+            }
+            let injected = inject(&html, &script);
+            // The length changed, and the validators no longer describe this body.
+            response.headers_mut().remove(header::CONTENT_LENGTH);
+            response.headers_mut().remove(header::ETAG);
+            response.headers_mut().remove(header::LAST_MODIFIED);
+            Ok(response.map_body(move |_, _| ResponseBody::Other(Body::from(injected))))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn inject_script() {
+        assert_eq!(&inject(b"<html><body>hi</BODY></html>", "<script></script>")[..], b"<html><body>hi<script></script></BODY></html>");
+        assert_eq!(&inject(b"hi", "<script></script>")[..], b"hi<script></script>");
+        let base = Path::new("/srv/dist");
+        assert_eq!(url_path(base, Path::new("/srv/dist/css/a b.css"), "/app"), Some("/app/css/a%20b.css".to_string()));
+        assert_eq!(url_path(base, Path::new("/srv/dist/.git/index"), ""), None);
+        assert_eq!(url_path(base, Path::new("/srv/dist/index.html~"), ""), None);
+    }
+
+    #[test]
+    fn forget_closed_clients() {
+        let dir = std::env::temp_dir().join(format!("happy_server_live_reload_{}_{:?}", std::process::id(), std::thread::current().id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let live_reload = LiveReload::watch(&dir, "").unwrap();
+        let (open, mut receiver) = mpsc::unbounded();
+        let (closed, _) = mpsc::unbounded();
+        live_reload.clients.lock().unwrap().extend(vec![open, closed]);
+        live_reload.broadcast(": ping\n\n");
+        assert_eq!(live_reload.clients.lock().unwrap().len(), 1);
+        assert_eq!(receiver.try_next().unwrap().unwrap().unwrap(), Bytes::from_static(b": ping\n\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
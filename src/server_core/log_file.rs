@@ -0,0 +1,183 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::listing;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// # When the log file is started over
+/// The current file is renamed with the time, e.g. "access.log.2021-07-10-123456", and a new one is opened.
+#[derive(Clone, Debug, Default)]
+pub struct Rotation {
+    /// Rotate before the file grows larger than this many bytes.
+    pub max_size: Option<u64>,
+    /// Rotate when the day changes, in UTC like the log lines.
+    pub daily: bool,
+    /// Compress the rotated files with gzip, adding ".gz".
+    pub compress: bool,
+    /// Delete the oldest rotated files beyond this many.
+    pub max_files: Option<usize>,
+}
+
+/// # Log file that is rotated as it grows
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    /// Day of the lines in the file, counted from 1970-01-01.
+    day: u64,
+    rotation: Rotation,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default()
+}
+
+impl LogFile {
+    /// # Open the file for appending
+    /// An existing file is continued, and rotated first if it is from an earlier day.
+    pub fn open(path: PathBuf, rotation: Rotation) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        let modified = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+            .unwrap_or_else(now);
+        Ok(Self{path, file, size: metadata.len(), day: modified / SECONDS_PER_DAY, rotation})
+    }
+    /// # Append a line, rotating the file first if needed
+    /// A failed rotation keeps writing to the current file.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let length = line.len() as u64 + 1;
+        let today = now() / SECONDS_PER_DAY;
+        let too_large = matches!(self.rotation.max_size, Some(max_size) if self.size > 0 && self.size + length > max_size);
+        let new_day = self.rotation.daily && self.size > 0 && today != self.day;
+        if too_large || new_day {
+            let _ = self.rotate();
+        }
+        self.day = today;
+        writeln!(self.file, "{}", line)?;
+        self.size += length;
+        Ok(())
+    }
+    /// # Start a new file
+    /// Returns the thread that compresses the rotated file and deletes the old ones.
+    fn rotate(&mut self) -> io::Result<JoinHandle<()>> {
+        let rotated = self.rotated_path();
+        fs::rename(&self.path, &rotated)?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        let path = self.path.clone();
+        let rotation = self.rotation.clone();
+        // Compressing a large file takes a while, the requests are not kept waiting.
+        Ok(std::thread::spawn(move || {
+            if rotation.compress {
+                let _ = compress(&rotated);
+            }
+            if let Some(max_files) = rotation.max_files {
+                let _ = remove_old_files(&path, max_files);
+            }
+        }))
+    }
+    /// # Name of the rotated file, which sorts by time
+    fn rotated_path(&self) -> PathBuf {
+        let unix_time = now();
+        let (year, month, day, seconds) = listing::civil_time(unix_time);
+        let stamp = format!("{:04}-{:02}-{:02}-{:02}{:02}{:02}", year, month, day, seconds / 3600, seconds % 3600 / 60, seconds % 60);
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}", stamp));
+        let rotated = self.path.with_file_name(&name);
+        // Rotated twice within a second
+        (1..).map(|number| match number {
+            1 => rotated.clone(),
+            number => {
+                let mut numbered = name.clone();
+                numbered.push(format!(".{}", number));
+                self.path.with_file_name(numbered)
+            },
+        }).find(|path| !path.exists() && !gz_path(path).exists()).unwrap_or(rotated)
+    }
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+fn compress(path: &Path) -> io::Result<()> {
+    let mut source = File::open(path)?;
+    let mut encoder = flate2::write::GzEncoder::new(File::create(gz_path(path))?, flate2::Compression::default());
+    io::copy(&mut source, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)
+}
+
+/// # Delete the oldest rotated files of the log beyond `max_files`
+/// Rotated files are recognized by the name of the log followed by the time.
+fn remove_old_files(path: &Path, max_files: usize) -> io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut rotated: Vec<((String, u32), PathBuf)> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| rotated_order(&name, &entry.file_name().to_string_lossy()).map(|order| (order, entry.path())))
+        .collect();
+    rotated.sort();
+    let excess = rotated.len().saturating_sub(max_files);
+    for (_, old) in &rotated[..excess] {
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+/// # Time and number of a rotated file, if the name is the log name followed by ".YYYY-MM-DD-HHMMSS"
+/// Files rotated within the same second are numbered from 2, e.g. "access.log.2021-07-10-123456.2.gz".
+fn rotated_order(log_name: &str, file_name: &str) -> Option<(String, u32)> {
+    let rest = file_name.strip_prefix(log_name)?.strip_prefix('.')?;
+    let stamp = rest.get(..17)?;
+    let valid = stamp.chars().enumerate().all(|(i, c)| match i {
+        4 | 7 | 10 => c == '-',
+        _ => c.is_ascii_digit(),
+    });
+    let number = rest[17..].trim_end_matches(".gz").strip_prefix('.').and_then(|number| number.parse::<u32>().ok()).unwrap_or(1);
+    match valid {
+        true => Some((stamp.to_string(), number)),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = std::env::temp_dir().join(format!("happy_server_log_{}_{:?}", std::process::id(), std::thread::current().id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let rotation = Rotation{max_size: Some(10), max_files: Some(1), ..Rotation::default()};
+        let mut log_file = LogFile::open(path.clone(), rotation).unwrap();
+        log_file.write_line("123456").unwrap();
+        log_file.write_line("abcdef").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abcdef\n");
+        // the older rotated file is deleted in the background
+        log_file.rotate().unwrap().join().unwrap();
+        log_file.write_line("ghijkl").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ghijkl\n");
+        let names: Vec<String> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        assert_eq!(names.len(), 2);
+        assert_eq!(rotated_order("access.log", "access.log.2021-07-10-123456.gz"), Some(("2021-07-10-123456".to_string(), 1)));
+        assert_eq!(rotated_order("access.log", "access.log.2021-07-10-123456.10.gz"), Some(("2021-07-10-123456".to_string(), 10)));
+        assert_eq!(rotated_order("access.log", "access.log.old"), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
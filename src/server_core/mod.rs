@@ -1,101 +1,508 @@
 // Copyright (c) 2021 Daichi Aoki  
 // Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
 
-use actix_files;
+mod access_log;
+mod archive;
+mod auth;
+mod compress;
+mod directory;
+mod event;
+mod ip_filter;
+mod listener;
+mod live_reload;
+mod listing;
+mod log_file;
+mod protect;
+mod proxy;
+mod share;
+mod spa;
+mod upload;
+mod webdav;
+mod zip;
+
 use actix_web::{App, HttpServer, dev::Server};
-use std::net::SocketAddrV4;
+use std::net::{IpAddr, SocketAddr};
 use std::result::Result;
 use std::io;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::io::BufRead;
+#[cfg(not(feature="no_ssl"))]
+use std::io::Read;
+use super::error::HappyServerError;
+use super::Language;
+use super::model::{check_uri_prefix, DEFAULT_HTTP_PORT, DEFAULT_IPV4_ADDR};
+pub use access_log::{AccessLog, AccessRecord, LogFormat};
+pub use auth::{Auth, Password};
+pub use compress::{Compression, Encoding, DEFAULT_COMPRESS_MIN_SIZE, DEFAULT_COMPRESS_TYPES};
+pub use directory::DEFAULT_INDEX_FILES;
+pub use event::{EventHandler, ServerEvent, UploadRejection};
+pub use ip_filter::{IpFilter, IpRange, LAN_RANGES};
+pub use listener::{interface_addrs, InterfaceUrl, ListenAddr, Listener};
+pub use listing::{format_time, human_size, LISTING_TEMPLATE};
+pub use log_file::Rotation;
+pub use proxy::Proxy;
+pub use share::{Share, ShareLink, DEFAULT_SHARE_EXPIRES};
+pub use spa::{Spa, DEFAULT_ASSET_EXTENSIONS};
+pub use upload::{Overwrite, Upload, DEFAULT_UPLOAD_MAX_REQUEST_SIZE, DEFAULT_UPLOAD_MAX_SIZE};
+pub use webdav::WebDav;
 
+/// # Settings of a happy server
+/// Either fill in the fields directly, or use the builder methods:
+/// `HappyServerBuilder::new(dir).port(8080).prefix("app").start()`
 pub struct HappyServerBuilder{
-    pub socket_addr: SocketAddrV4,
+    /// Addresses to listen on. All of them deliver the same directory and stop together.
+    /// The unspecified IPv6 address `::` listens on both IPv4 and IPv6.
+    pub listeners: Vec<Listener>,
+    /// Directory to deliver.
     pub distribution_dir: PathBuf,
+    /// The directory is delivered under `/{uri_prefix}`.
     pub uri_prefix: String,
+    /// Certificate for the listeners that deliver via https.
     pub ssl: Option<Ssl>,
+    /// If a port is already in use, try the following ports.
+    /// After starting, `listeners` hold the ports actually used.
+    pub auto_port: bool,
+    /// File names tried in order when a directory is requested, e.g. `DEFAULT_INDEX_FILES`.
+    pub index_files: Vec<String>,
+    /// Show the files of a directory without an index file.
+    /// If false, such a directory is answered with 403 Forbidden.
+    pub listing: bool,
+    /// Custom HTML template of the listing, with the placeholders of `LISTING_TEMPLATE`.
+    pub listing_template: Option<String>,
+    /// Language of the listing and error pages.
+    pub language: Language,
+    /// Deliver a single-page application, see `Spa`.
+    pub spa: Option<Spa>,
+    /// Accept uploads, see `Upload`.
+    pub upload: Option<Upload>,
+    /// Answer WebDAV requests, see `WebDav`.
+    pub webdav: Option<WebDav>,
+    /// Require a user name and password, see `Auth`.
+    pub auth: Option<Auth>,
+    /// Refuse clients by address, see `IpFilter`.
+    pub ip_filter: Option<IpFilter>,
+    /// Deliver only files requested with a signed link, see `Share`.
+    pub share: Option<Share>,
+    /// Reload the browsers when a file of the distribution directory changes.
+    /// A script is added to the HTML pages, and changed stylesheets are swapped without reloading.
+    pub watch: bool,
+    /// Write a line per request, see `AccessLog`.
+    pub access_log: Option<AccessLog>,
+    /// Compress responses on the fly, see `Compression`.
+    pub compression: Option<Compression>,
+    /// Deliver "main.js.br", "main.js.zst" or "main.js.gz" for "main.js" when the client accepts it.
+    pub precompressed: bool,
+    /// Forward the requests below some paths to other servers, see `Proxy`.
+    pub proxies: Vec<Proxy>,
+    /// Files of the distribution directory that are never delivered, e.g. the config file.
+    pub protected_files: Vec<PathBuf>,
+    /// Called with the events of the running server, e.g. uploads.
+    pub on_event: Option<EventHandler>,
 }
+/// # PEM encoded certificate and private key
 pub struct Ssl {
     pub server_crt: Box<dyn BufRead>,
     pub server_key: Box<dyn BufRead>,
+    /// SHA-256 fingerprint of the certificate, if happy server generated it.
+    pub fingerprint: Option<String>,
 }
 
-#[cfg(feature="no_ssl")]
-fn start_server(builder: HappyServer) -> Result<Server, ()> {
-    match builder.ssl.as_mut() {
-        Some(ssl) => {
-            Err(())
-        }
-        None=> {
-            let distribution_dir = Arc::new(builder.distribution_dir.clone());
-            let uri_prefix = Arc::new(builder.uri_prefix.clone());
-            let server = Ok(HttpServer::new(move|| {
-                App::new().service(actix_files::Files::new(&uri_prefix, &*distribution_dir).show_files_listing())
-            })
-            .bind(&builder.socket_addr).unwrap()
-            .run());
-            server
-        }
+/// # Build the TLS settings from the PEM encoded certificate and private key
+/// PKCS#8 keys are tried first, then RSA (PKCS#1) keys.
+#[cfg(not(feature="no_ssl"))]
+fn rustls_config(ssl: &mut Ssl) -> Result<rustls::ServerConfig, HappyServerError> {
+    let certs = rustls::internal::pemfile::certs(&mut ssl.server_crt).map_err(|_| HappyServerError::CertParse)?;
+    if certs.is_empty() {
+        return Err(HappyServerError::CertParse);
+    }
+    let mut server_key = Vec::new();
+    ssl.server_key.read_to_end(&mut server_key).map_err(|_| HappyServerError::KeyParse)?;
+    let mut keys = rustls::internal::pemfile::pkcs8_private_keys(&mut server_key.as_slice()).map_err(|_| HappyServerError::KeyParse)?;
+    if keys.is_empty() {
+        keys = rustls::internal::pemfile::rsa_private_keys(&mut server_key.as_slice()).map_err(|_| HappyServerError::KeyParse)?;
+    }
+    if keys.is_empty() {
+        return Err(HappyServerError::EmptyKeyList);
     }
+    let mut cfg = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+    cfg.set_single_cert(certs, keys.remove(0)).map_err(HappyServerError::InvalidCertificate)?;
+    cfg.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Ok(cfg)
 }
-#[cfg(not(feature="no_ssl"))]
-fn start_server(builder: &mut HappyServerBuilder) -> Result<Server, ()> {
-    match builder.ssl.as_mut() {
-        Some(ssl) => {
-            let certs = rustls::internal::pemfile::certs(&mut ssl.server_crt).unwrap();
-            let mut keys = rustls::internal::pemfile::pkcs8_private_keys(&mut ssl.server_key).unwrap();
-            let mut cfg = rustls::ServerConfig::new(rustls::NoClientAuth::new());
-            cfg.set_single_cert(certs, keys.remove(0)).unwrap();
-            cfg.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
 
-            let distribution_dir = Arc::new(builder.distribution_dir.clone());
-            let uri_prefix = Arc::new(builder.uri_prefix.clone());
+/// # Distinguish a missing permission from other bind failures
+fn bind_error(listen_addr: &ListenAddr, e: io::Error) -> HappyServerError {
+    match e.kind() {
+        io::ErrorKind::PermissionDenied => HappyServerError::PermissionDenied(listen_addr.clone()),
+        _ => HappyServerError::Bind(listen_addr.clone(), e),
+    }
+}
 
-            let server = Ok(HttpServer::new(move|| {
-                App::new().service(actix_files::Files::new(&uri_prefix, &*distribution_dir).show_files_listing())
-            })
-            .bind_rustls(&builder.socket_addr, cfg).unwrap()
-            .run());
-            server
+fn start_server(builder: &mut HappyServerBuilder) -> Result<Server, HappyServerError> {
+    let distribution_dir = Arc::new(builder.distribution_dir.clone());
+    let uri_prefix = Arc::new(builder.uri_prefix.clone());
+    let spa = builder.spa.clone().map(Arc::new);
+    let protected_files = protect::ProtectedFiles::new(&builder.protected_files, &builder.distribution_dir, &builder.uri_prefix);
+    let directory_settings = Arc::new(directory::DirectorySettings{
+        index_files: builder.index_files.clone(),
+        listing: builder.listing,
+        spa: spa.clone(),
+        uri_prefix: builder.uri_prefix.clone(),
+        language: builder.language,
+        listing_template: builder.listing_template.clone(),
+        upload: builder.upload.is_some(),
+        protected_files: protected_files.clone(),
+    });
+    // WebDAV clients save files with PUT, which replaces existing files unless uploads are configured otherwise.
+    let upload_settings = Arc::new(upload::UploadSettings{
+        distribution_dir: builder.distribution_dir.clone(),
+        uri_prefix: builder.uri_prefix.clone(),
+        upload: builder.upload.clone().unwrap_or(Upload{overwrite: Overwrite::Replace, ..Upload::default()}),
+        language: builder.language,
+        protected_files: protected_files.clone(),
+        on_event: builder.on_event.clone(),
+    });
+    let upload = builder.upload.is_some();
+    let proxies: Vec<Arc<Proxy>> = builder.proxies.iter().cloned().map(Arc::new).collect();
+    let webdav_settings = builder.webdav.map(|webdav| Arc::new(webdav::WebDavSettings{webdav, upload: upload_settings.clone(), listing: builder.listing}));
+    // Shared by the workers, so that a password is checked once.
+    let authentication = auth::Authentication::new(builder.auth.clone());
+    let share_links = share::ShareLinks::new(builder.share.clone(), builder.uri_prefix.clone(), builder.language);
+    let access_control = ip_filter::AccessControl::new(builder.ip_filter.clone(), builder.on_event.clone());
+    let live_reload = match builder.watch {
+        true => Some(live_reload::LiveReload::watch(&builder.distribution_dir, &builder.uri_prefix).map_err(|e| HappyServerError::Watch(e.to_string()))?),
+        false => None,
+    };
+    let script_injector = live_reload::ScriptInjector::new(live_reload.clone());
+    let precompressed = match builder.precompressed {
+        true => Some(compress::Precompressed{
+            distribution_dir: builder.distribution_dir.clone(),
+            uri_prefix: builder.uri_prefix.clone(),
+            proxy_paths: builder.proxies.iter().map(|proxy| proxy.path.clone()).collect(),
+        }),
+        false => None,
+    };
+    let compressor = compress::Compressor::new(builder.compression.clone(), precompressed);
+    let access_logger = access_log::AccessLogger::new(builder.access_log.clone(), builder.on_event.clone())
+        .map_err(|e| HappyServerError::AccessLogFile(builder.access_log.as_ref().and_then(|access_log| access_log.file.clone()).unwrap_or_default(), e))?;
+    let mut http_server = HttpServer::new(move|| {
+        // Directories are always handed to the renderer, which picks the index file, the listing or 403.
+        let directory_settings = directory_settings.clone();
+        let files = actix_files::Files::new(&uri_prefix, &*distribution_dir)
+            .show_files_listing()
+            .files_listing_renderer(move |dir, req| directory::render(&directory_settings, dir, req));
+        let files = match &spa {
+            Some(spa) => files.default_handler(spa::fallback(distribution_dir.clone(), spa.clone())),
+            None => files,
+        };
+        // Proxies, uploads and WebDAV are matched first, the files service only answers GET and HEAD.
+        // The last middleware runs first: every request is logged, then the address is checked,
+        // then protected files are refused, then the links, then the password, as a valid link needs none.
+        // Pages are compressed after the live reload script is added.
+        let mut app = App::new().wrap(script_injector.clone()).wrap(compressor.clone()).wrap(authentication.clone()).wrap(share_links.clone())
+            .wrap(protected_files.clone()).wrap(access_control.clone()).wrap(access_logger.clone());
+        if let Some(live_reload) = &live_reload {
+            app = app.service(live_reload::resource(live_reload.clone()));
         }
-        None=> {
-            let distribution_dir = Arc::new(builder.distribution_dir.clone());
-            let uri_prefix = Arc::new(builder.uri_prefix.clone());
-            let server = Ok(HttpServer::new(move|| {
-                App::new().service(actix_files::Files::new(&uri_prefix, &*distribution_dir).show_files_listing())
-            })
-            .bind(&builder.socket_addr).unwrap()
-            .run());
-            server
+        for proxy in &proxies {
+            app = app.service(proxy::scope(proxy.clone()));
         }
+        if upload {
+            app = app.service(upload::resource(upload_settings.clone()));
+        }
+        if let Some(webdav_settings) = &webdav_settings {
+            app = app.service(webdav::resource(webdav_settings.clone()));
+        }
+        app.service(files)
+    });
+
+    // The TLS settings are shared by every https listener.
+    #[cfg(not(feature="no_ssl"))]
+    let tls_config = match builder.ssl.as_mut() {
+        Some(ssl) => Some(rustls_config(ssl)?),
+        None => None,
+    };
+
+    let auto_port = builder.auto_port;
+    for listener in builder.listeners.iter_mut() {
+        http_server = match &listener.addr {
+            ListenAddr::Tcp(socket_addr) => {
+                let tcp_listener = listener::listen(*socket_addr, auto_port).map_err(|e| bind_error(&listener.addr, e))?;
+                // With port 0 or auto port, the bound port differs from the requested one.
+                if let Ok(local_addr) = tcp_listener.local_addr() {
+                    listener.addr = ListenAddr::Tcp(local_addr);
+                }
+                match listener.tls {
+                    false => http_server.listen(tcp_listener),
+                    #[cfg(not(feature="no_ssl"))]
+                    true => match &tls_config {
+                        Some(cfg) => http_server.listen_rustls(tcp_listener, cfg.clone()),
+                        None => return Err(HappyServerError::MissingCertificate(listener.addr.clone())),
+                    },
+                    #[cfg(feature="no_ssl")]
+                    true => return Err(HappyServerError::SslDisabled),
+                }
+            },
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                listener::remove_stale_socket(path);
+                http_server.bind_uds(path)
+            },
+        }.map_err(|e| bind_error(&listener.addr, e))?;
     }
+    Ok(http_server.run())
 }
 
 impl HappyServerBuilder {
-    pub async fn start_server(&mut self) -> Result<Server,()> {
+    /// # Deliver a directory
+    /// By default the server listens on all interfaces on port 80 without a uri prefix.
+    pub fn new(distribution_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            listeners: vec![Listener::http(SocketAddr::new(IpAddr::V4(DEFAULT_IPV4_ADDR), DEFAULT_HTTP_PORT))],
+            distribution_dir: distribution_dir.into(),
+            uri_prefix: String::new(),
+            ssl: None,
+            auto_port: false,
+            index_files: Vec::new(),
+            listing: true,
+            listing_template: None,
+            language: Language::English,
+            spa: None,
+            upload: None,
+            webdav: None,
+            auth: None,
+            ip_filter: None,
+            share: None,
+            watch: false,
+            access_log: None,
+            compression: None,
+            precompressed: false,
+            proxies: Vec::new(),
+            protected_files: Vec::new(),
+            on_event: None,
+        }
+    }
+    /// # Set the port of the first listener
+    /// Port 0 lets the OS choose a free port, see `HappyServer::addrs` for the chosen one.
+    pub fn port(mut self, port: u16) -> Self {
+        if let Some(Listener{addr: ListenAddr::Tcp(socket_addr), ..}) = self.listeners.first_mut() {
+            socket_addr.set_port(port);
+        }
+        self
+    }
+    /// # Set the address of the first listener
+    /// e.g. `Ipv4Addr::LOCALHOST` to deliver only to this machine, `Ipv6Addr::UNSPECIFIED` for dual-stack.
+    pub fn bind(mut self, ip: IpAddr) -> Self {
+        if let Some(Listener{addr: ListenAddr::Tcp(socket_addr), ..}) = self.listeners.first_mut() {
+            socket_addr.set_ip(ip);
+        }
+        self
+    }
+    /// # Try the following ports if a port is already in use
+    pub fn auto_port(mut self, auto_port: bool) -> Self {
+        self.auto_port = auto_port;
+        self
+    }
+    /// # Listen on another address as well
+    /// e.g. `.port(8443).ssl(ssl).listen(Listener::http(addr))` delivers via https on 8443 and via http on `addr`.
+    pub fn listen(mut self, listener: Listener) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+    /// # Set the uri prefix
+    /// "app" delivers the directory under `/app/`.
+    pub fn prefix(mut self, uri_prefix: impl Into<String>) -> Self {
+        self.uri_prefix = uri_prefix.into();
+        self
+    }
+    /// # Deliver via https with the certificate and private key
+    /// The first listener is switched to https.
+    pub fn ssl(mut self, ssl: Ssl) -> Self {
+        if let Some(listener) = self.listeners.first_mut() {
+            listener.tls = true;
+        }
+        self.ssl = Some(ssl);
+        self
+    }
+    /// # Set the file names tried when a directory is requested
+    /// e.g. `.index_files(DEFAULT_INDEX_FILES.iter())` delivers index.html or index.htm instead of the listing.
+    pub fn index_files<I: IntoIterator>(mut self, index_files: I) -> Self where I::Item: ToString {
+        self.index_files = index_files.into_iter().map(|index_file| index_file.to_string()).collect();
+        self
+    }
+    /// # Show or hide the files of directories without an index file
+    pub fn listing(mut self, listing: bool) -> Self {
+        self.listing = listing;
+        self
+    }
+    /// # Use a custom HTML template for the listing
+    /// The template is the contents of the page, see `LISTING_TEMPLATE` for the placeholders.
+    pub fn listing_template(mut self, template: impl Into<String>) -> Self {
+        self.listing_template = Some(template.into());
+        self
+    }
+    /// # Set the language of the listing and error pages
+    pub fn language(mut self, language: Language) -> Self {
+        self.language = language;
+        self
+    }
+    /// # Deliver a single-page application
+    /// e.g. `.spa(Spa::default())` returns `index.html` for every path that is not a file or an asset.
+    pub fn spa(mut self, spa: Spa) -> Self {
+        self.spa = Some(spa);
+        self
+    }
+    /// # Accept uploads into the distribution directory
+    /// e.g. `.upload(Upload::default())` accepts files up to 1 GiB that do not exist yet.
+    pub fn upload(mut self, upload: Upload) -> Self {
+        self.upload = Some(upload);
+        self
+    }
+    /// # Answer WebDAV requests so that file managers can mount the directory
+    /// e.g. `.webdav(WebDav{writable: true})` also lets them change files.
+    pub fn webdav(mut self, webdav: WebDav) -> Self {
+        self.webdav = Some(webdav);
+        self
+    }
+    /// # Require a user name and password
+    /// e.g. `.auth(Auth::default().user("alice", Password::Plain("secret".to_string())))`.
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+    /// # Refuse clients by address
+    /// e.g. `.ip_filter(IpFilter::lan_only())` to allow only the local network.
+    pub fn ip_filter(mut self, ip_filter: IpFilter) -> Self {
+        self.ip_filter = Some(ip_filter);
+        self
+    }
+    /// # Deliver only files requested with a signed link
+    /// e.g. `.share(Share{secret, links: vec![ShareLink{path: "report.pdf".to_string(), expires, max_downloads: None}]})`.
+    pub fn share(mut self, share: Share) -> Self {
+        self.share = Some(share);
+        self
+    }
+    /// # Reload the browsers when a file changes
+    pub fn watch(mut self, watch: bool) -> Self {
+        self.watch = watch;
+        self
+    }
+    /// # Write a line per request
+    /// e.g. `.access_log(AccessLog{format: LogFormat::Combined, file: Some("access.log".into()), rotation: Rotation::default()})`.
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+    /// # Compress responses on the fly
+    /// e.g. `.compression(Compression::default())` compresses text, scripts and JSON of 1 KiB and more.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+    /// # Deliver precompressed files next to the requested ones
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.precompressed = precompressed;
+        self
+    }
+    /// # Forward the requests below a path to another server
+    /// e.g. `.proxy(Proxy::parse("/api=http://127.0.0.1:3000").unwrap())`, repeatable.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+    /// # Never deliver a file of the distribution directory
+    /// Requests for it are answered with 404 Not Found, and it is left out of listings and archives. Repeatable.
+    pub fn protect(mut self, file: impl Into<PathBuf>) -> Self {
+        self.protected_files.push(file.into());
+        self
+    }
+    /// # Receive the events of the running server
+    /// The handler is called on the worker threads.
+    pub fn on_event(mut self, on_event: impl Fn(ServerEvent) + Send + Sync + 'static) -> Self {
+        self.on_event = Some(Arc::new(on_event));
+        self
+    }
+    /// # Check the settings and start the server
+    /// Unlike `start_server`, the builder is moved into the returned `HappyServer`.
+    pub async fn start(mut self) -> Result<HappyServer, HappyServerError> {
+        self.uri_prefix = check_uri_prefix(self.uri_prefix)?;
+        let server = start_server(&mut self)?;
+        Ok(HappyServer{server, hs_builder: self})
+    }
+    /// # Start the server
+    /// The builder is kept so that it can be passed to `HappyServerViewer::output_start_server`.
+    pub async fn start_server(&mut self) -> Result<Server, HappyServerError> {
         start_server(self)
     }
+    /// # URLs to browse the delivered directory, one per listener
+    pub fn urls(&self) -> Vec<String> {
+        self.listeners.iter().map(|listener| listener.url(&self.uri_prefix)).collect()
+    }
+    /// # URLs for the other devices on the network, per interface
+    pub fn interface_urls(&self) -> Vec<InterfaceUrl> {
+        self.listeners.iter().flat_map(|listener| listener.interface_urls(&self.uri_prefix)).collect()
+    }
+    /// # URL of the first listener
+    pub fn url(&self) -> String {
+        self.urls().into_iter().next().unwrap_or_default()
+    }
+    /// # Signed links of the shared files
+    /// They point to the first interface, so that they can be handed to other devices, or to the first listener.
+    pub fn share_urls(&self) -> Vec<(ShareLink, String)> {
+        let share = match &self.share {
+            Some(share) => share,
+            None => return Vec::new(),
+        };
+        let url = self.interface_urls().into_iter().next().map(|interface_url| interface_url.url).unwrap_or_else(|| self.url());
+        let origin = url.trim_end_matches(self.uri_prefix.as_str()).trim_end_matches('/');
+        share.links.iter().map(|link| (link.clone(), format!("{}{}", origin, share.link(&self.uri_prefix, link)))).collect()
+    }
+    /// # URL schemes of the listeners
+    /// "http", "https", or "http/https" if both are used.
+    pub fn scheme(&self) -> &'static str {
+        let tls = self.listeners.iter().filter(|listener| listener.tls).count();
+        match tls {
+            0 => "http",
+            tls if tls == self.listeners.len() => "https",
+            _ => "http/https",
+        }
+    }
 }
 
 
+/// # Output the state of the server
 pub trait HappyServerViewer {
-    fn output_start_server(&mut self, hs_server: &Result<Server, ()>, hs_builder: &HappyServerBuilder) -> io::Result<()>;
+    fn output_start_server(&mut self, hs_server: &Result<Server, HappyServerError>, hs_builder: &HappyServerBuilder) -> io::Result<()>;
     fn output_server_stop(&mut self, hs_server: &HappyServer) -> io::Result<()>;
+    /// # Output an event of the running server
+    /// Events are ignored unless the viewer shows them.
+    fn output_event(&mut self, _event: &ServerEvent) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 
+/// # Running happy server
 pub struct HappyServer {
     pub server: Server,
     pub hs_builder: HappyServerBuilder
 }
 
-#[allow(dead_code)]
 impl HappyServer {
+    /// # Addresses the server is listening on
+    /// Ports chosen by the OS or by auto port are filled in.
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        self.hs_builder.listeners.iter().filter_map(|listener| match listener.addr {
+            ListenAddr::Tcp(socket_addr) => Some(socket_addr),
+            #[cfg(unix)]
+            ListenAddr::Unix(_) => None,
+        }).collect()
+    }
     pub async fn stop(& self) {
         self.server.stop(false).await;
     }
     pub async fn awaiting(self) -> std::io::Result<()>{
         self.server.await
     }
-}
\ No newline at end of file
+}
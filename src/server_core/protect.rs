@@ -0,0 +1,152 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::upload;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::Uri;
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, Either, Ready};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Methods that change or remove a whole directory tree.
+const TREE_METHODS: [&str; 3] = ["COPY", "MOVE", "DELETE"];
+
+/// # Files in the distribution directory that are never delivered
+/// e.g. the config file, which may hold passwords and the secret of the share links.
+/// The files are compared by their canonical paths, so links to them are refused as well.
+#[derive(Clone, Default)]
+pub struct ProtectedFiles {
+    files: Arc<Vec<PathBuf>>,
+    distribution_dir: PathBuf,
+    uri_prefix: String,
+}
+
+impl ProtectedFiles {
+    /// Files that do not exist are left out, there is nothing to deliver.
+    pub fn new(files: &[PathBuf], distribution_dir: &Path, uri_prefix: &str) -> Self {
+        let files = files.iter().filter_map(|file| file.canonicalize().ok()).collect();
+        Self{files: Arc::new(files), distribution_dir: distribution_dir.to_path_buf(), uri_prefix: uri_prefix.to_string()}
+    }
+    /// # Whether the path is a protected file
+    pub fn contains(&self, path: &Path) -> bool {
+        !self.files.is_empty() && path.canonicalize().map(|path| self.files.contains(&path)).unwrap_or(false)
+    }
+    /// # Whether the path is a protected file or a directory with one inside
+    fn within(&self, path: &Path) -> bool {
+        !self.files.is_empty() && path.canonicalize().map(|path| self.files.iter().any(|file| file.starts_with(&path))).unwrap_or(false)
+    }
+    /// # Path below the distribution directory of a URL path
+    fn resolve(&self, url_path: &str) -> Option<PathBuf> {
+        let url_path = url_path.trim_start_matches('/');
+        let tail = match self.uri_prefix.trim_end_matches('/') {
+            "" => url_path,
+            uri_prefix => url_path.strip_prefix(uri_prefix).filter(|tail| tail.is_empty() || tail.starts_with('/'))?,
+        };
+        Some(self.distribution_dir.join(upload::safe_path(tail)?))
+    }
+    /// # Whether the request reads or changes a protected file
+    /// Copying, moving or deleting a directory is refused if a protected file is inside,
+    /// as is copying or moving anything onto one.
+    fn refuses(&self, req: &ServiceRequest) -> bool {
+        let tree_method = TREE_METHODS.contains(&req.method().as_str());
+        let target = self.resolve(req.path());
+        let destination = req.headers().get("Destination")
+            .and_then(|destination| destination.to_str().ok())
+            .and_then(|destination| destination.parse::<Uri>().ok())
+            .and_then(|destination| self.resolve(destination.path()));
+        match (target, destination) {
+            (Some(target), _) if self.contains(&target) || (tree_method && self.within(&target)) => true,
+            (_, Some(destination)) => self.within(&destination),
+            _ => false,
+        }
+    }
+}
+
+impl<S, B> Transform<S> for ProtectedFiles
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ProtectedFilesMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ProtectedFilesMiddleware{service, protected_files: self.clone()})
+    }
+}
+
+/// # Middleware that answers requests for protected files with 404 Not Found
+/// It runs before the files service and the precompressed files, so neither can deliver them.
+pub struct ProtectedFilesMiddleware<S> {
+    service: S,
+    protected_files: ProtectedFiles,
+}
+
+impl<S, B> Service for ProtectedFilesMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if !self.protected_files.refuses(&req) {
+            return Either::Left(self.service.call(req));
+        }
+        let response = HttpResponse::NotFound().content_type("text/plain; charset=utf-8").body("404 Not Found");
+        Either::Right(ok(req.into_response(response).map_body(|_, body| body.into_body())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::compress::{Compressor, Precompressed};
+    use actix_web::{test, App};
+
+    #[test]
+    fn refuse_config_file() {
+        let dir = std::env::temp_dir().join(format!("happy_server_protect_{}_{:?}", std::process::id(), std::thread::current().id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("happy_server.toml"), "share_secret = \"secret\"\n").unwrap();
+        std::fs::write(dir.join("happy_server.toml.br"), "precompressed").unwrap();
+        std::fs::write(dir.join("sub/index.txt"), "index").unwrap();
+        let protected_files = ProtectedFiles::new(&[dir.join("happy_server.toml")], &dir, "");
+        let distribution_dir = dir.clone();
+        actix_web::rt::System::new("refuse_config_file").block_on(async move {
+            let precompressed = Precompressed{distribution_dir: distribution_dir.clone(), uri_prefix: String::new(), proxy_paths: Vec::new()};
+            let app = App::new().wrap(Compressor::new(None, Some(precompressed))).wrap(protected_files);
+            let mut app = test::init_service(app.service(actix_files::Files::new("/", &distribution_dir))).await;
+            for (method, path, destination, status) in [
+                ("GET", "/happy_server.toml", None, 404),
+                ("HEAD", "/happy_server%2Etoml", None, 404),
+                ("DELETE", "/", None, 404),
+                ("MOVE", "/sub/index.txt", Some("http://localhost/happy_server.toml"), 404),
+                ("GET", "/sub/index.txt", None, 200),
+            ].iter() {
+                let mut request = test::TestRequest::with_uri(path).method(method.parse().unwrap()).header("Accept-Encoding", "br");
+                if let Some(destination) = destination {
+                    request = request.header("Destination", *destination);
+                }
+                let response = test::call_service(&mut app, request.to_request()).await;
+                assert_eq!(response.status().as_u16(), *status, "{} {}", method, path);
+            }
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
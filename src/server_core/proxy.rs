@@ -0,0 +1,305 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use actix_codec::{BytesCodec, Framed};
+use super::auth::Authenticated;
+use actix_web::client::{Client, Connector};
+use actix_web::dev::{Body, SizedStream};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode, Uri};
+use actix_web::{web, Error, HttpRequest, HttpResponse, Scope};
+use futures::{SinkExt, StreamExt, TryStreamExt};
+use ring::digest;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Added to the key of a WebSocket handshake before hashing it, see RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// A target that can not be reached within this time is answered with 502 Bad Gateway.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest wait for the response of a request without a body, e.g. a long poll.
+/// Requests with a body have no limit, uploading it may take long.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// # Forward the requests below a path to another server
+/// e.g. path "/api" and target "http://127.0.0.1:3000" forward "/api/users?page=2" to "http://127.0.0.1:3000/api/users?page=2".
+/// If the target has a path, it replaces the proxy path: with "http://127.0.0.1:3000/v1", "/api/users" goes to "/v1/users".
+/// The path is matched from the root, independent of the uri prefix, and before the files.
+/// `Location` headers that point to the target are rewritten to the proxy path, and WebSocket connections are passed through.
+/// The `Authorization` header is not passed on when this server asked for the password with `--auth`.
+/// Cookies are passed on as they are, so the target sees the cookies of every path on this host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proxy {
+    /// Path without a trailing "/", e.g. "/api".
+    pub path: String,
+    /// "http" or "https" URL without a trailing "/", e.g. "http://127.0.0.1:3000".
+    pub target: String,
+}
+
+impl Proxy {
+    /// # Parse a rule such as "/api=http://127.0.0.1:3000"
+    pub fn parse(rule: &str) -> Option<Self> {
+        let (path, target) = rule.trim().split_once('=')?;
+        let path = path.trim().trim_end_matches('/');
+        let target = target.trim().trim_end_matches('/');
+        if !path.starts_with('/') || path.contains(['?', '#']) {
+            return None;
+        }
+        let uri = target.parse::<Uri>().ok()?;
+        match (uri.scheme_str(), uri.host(), uri.query()) {
+            (Some("http"), Some(_), None) | (Some("https"), Some(_), None) => Some(Self{path: path.to_string(), target: target.to_string()}),
+            _ => None,
+        }
+    }
+    /// # Scheme and authority of the target, e.g. "http://127.0.0.1:3000"
+    fn origin(&self) -> &str {
+        let authority_start = self.target.find("://").map(|i| i + 3).unwrap_or(0);
+        match self.target[authority_start..].find('/') {
+            Some(i) => &self.target[..authority_start + i],
+            None => &self.target,
+        }
+    }
+    /// # Path of the target, "" if it has none
+    fn base(&self) -> &str {
+        &self.target[self.origin().len()..]
+    }
+    /// # URL of the target for a requested path and query
+    fn target_url(&self, path: &str, query: &str) -> String {
+        let path = match self.base() {
+            "" => path.to_string(),
+            base => format!("{}{}", base, path.strip_prefix(self.path.as_str()).unwrap_or(path)),
+        };
+        let path = if path.is_empty() {"/"} else {&path};
+        match query {
+            "" => format!("{}{}", self.origin(), path),
+            query => format!("{}{}?{}", self.origin(), path, query),
+        }
+    }
+    /// # Location that points to the target, as seen through the proxy
+    /// None if it points elsewhere.
+    fn rewrite_location(&self, location: &str) -> Option<String> {
+        let origin = self.origin();
+        let path = match location.get(..origin.len()) {
+            Some(start) if start.eq_ignore_ascii_case(origin) => &location[origin.len()..],
+            // A relative location is already on this server, unless the target path has to be replaced.
+            _ if location.starts_with('/') && !self.base().is_empty() => location,
+            _ => return None,
+        };
+        if !(path.is_empty() || path.starts_with(['/', '?', '#'])) {
+            return None;
+        }
+        match self.base() {
+            "" => Some(if path.is_empty() {"/".to_string()} else {path.to_string()}),
+            base => {
+                let rest = path.strip_prefix(base)?;
+                match rest.is_empty() || rest.starts_with(['/', '?', '#']) {
+                    true => Some(format!("{}{}", self.path, rest)),
+                    false => None,
+                }
+            },
+        }
+    }
+}
+
+/// # Headers of a single connection, which are not passed on
+/// Headers named in `Connection` are left out as well.
+fn hop_by_hop(name: &HeaderName, headers: &HeaderMap) -> bool {
+    matches!(name.as_str(), "connection" | "keep-alive" | "proxy-authenticate" | "proxy-authorization" | "te" | "trailer" | "transfer-encoding" | "upgrade")
+        || headers.get_all(header::CONNECTION)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|connection| connection.trim().eq_ignore_ascii_case(name.as_str()))
+}
+
+/// # Headers of the request for the target
+/// `Host` is the one of the target, the original one is passed as `X-Forwarded-Host`.
+/// The password of this server is kept from the target.
+fn request_headers(proxy: &Proxy, req: &HttpRequest) -> Vec<(HeaderName, HeaderValue)> {
+    let authenticated = req.extensions().contains::<Authenticated>();
+    let mut headers: Vec<(HeaderName, HeaderValue)> = req.headers().iter()
+        .filter(|(name, _)| !hop_by_hop(name, req.headers()) && **name != header::HOST && **name != header::CONTENT_LENGTH)
+        .filter(|(name, _)| !(authenticated && **name == header::AUTHORIZATION))
+        .filter(|(name, _)| !name.as_str().starts_with("x-forwarded-"))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    let authority = &proxy.origin()[proxy.origin().find("://").map(|i| i + 3).unwrap_or(0)..];
+    if let Ok(host) = HeaderValue::from_str(authority) {
+        headers.push((header::HOST, host));
+    }
+    let forwarded_for = req.headers().get("x-forwarded-for").and_then(|value| value.to_str().ok());
+    let forwarded_for = match (forwarded_for, req.peer_addr()) {
+        (Some(forwarded_for), Some(peer_addr)) => Some(format!("{}, {}", forwarded_for, peer_addr.ip())),
+        (Some(forwarded_for), None) => Some(forwarded_for.to_string()),
+        (None, Some(peer_addr)) => Some(peer_addr.ip().to_string()),
+        (None, None) => None,
+    };
+    let forwarded = [
+        ("x-forwarded-for", forwarded_for),
+        ("x-forwarded-host", req.headers().get(header::HOST).and_then(|host| host.to_str().ok()).map(String::from)),
+        ("x-forwarded-proto", Some(req.connection_info().scheme().to_string())),
+    ];
+    for (name, value) in forwarded.iter() {
+        if let Some(value) = value.as_ref().and_then(|value| HeaderValue::from_str(value).ok()) {
+            headers.push((HeaderName::from_static(name), value));
+        }
+    }
+    headers
+}
+
+/// # Headers of the target's response, with `Location` rewritten
+fn response_headers<'a>(proxy: &'a Proxy, headers: &'a HeaderMap) -> impl Iterator<Item = (HeaderName, HeaderValue)> + 'a {
+    headers.iter()
+        .filter(move |(name, _)| !hop_by_hop(name, headers) && **name != header::CONTENT_LENGTH)
+        .map(move |(name, value)| match (*name == header::LOCATION, value.to_str().ok().and_then(|location| proxy.rewrite_location(location))) {
+            (true, Some(location)) => (name.clone(), HeaderValue::from_str(&location).unwrap_or_else(|_| value.clone())),
+            _ => (name.clone(), value.clone()),
+        })
+}
+
+fn bad_gateway(e: impl std::fmt::Display) -> HttpResponse {
+    HttpResponse::BadGateway().content_type("text/plain; charset=utf-8").body(format!("502 Bad Gateway: {}", e))
+}
+
+/// # Forward a request and stream the response back
+async fn forward(proxy: &Proxy, client: &Client, req: HttpRequest, payload: web::Payload) -> HttpResponse {
+    let url = proxy.target_url(req.uri().path(), req.query_string());
+    let mut request = client.request(req.method().clone(), url).no_decompress();
+    for (name, value) in request_headers(proxy, &req) {
+        request = request.header(name, value);
+    }
+    let length = req.headers().get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok());
+    let payload = payload.map_err(Error::from);
+    // The body keeps its length, or stays chunked.
+    let response = match (length, req.headers().contains_key(header::TRANSFER_ENCODING)) {
+        (Some(length), _) if length > 0 => request.send_body(SizedStream::new(length, payload)).await,
+        (None, true) => request.send_stream(payload).await,
+        _ => request.timeout(RESPONSE_TIMEOUT).send().await,
+    };
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => return bad_gateway(e),
+    };
+    let mut builder = HttpResponse::build(response.status());
+    for (name, value) in response_headers(proxy, response.headers()) {
+        builder.header(name, value);
+    }
+    let length = response.headers().get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok());
+    let bodiless = req.method() == Method::HEAD || response.status().is_informational()
+        || response.status() == StatusCode::NO_CONTENT || response.status() == StatusCode::NOT_MODIFIED;
+    match (length, bodiless) {
+        (Some(length), _) => builder.body(SizedStream::new(length, response.map_err(Error::from))),
+        (None, true) => builder.body(Body::None),
+        (None, false) => builder.streaming(response),
+    }
+}
+
+/// # Whether the request asks to switch to the WebSocket protocol
+fn websocket(req: &HttpRequest) -> bool {
+    req.head().upgrade() && req.headers().get(header::UPGRADE)
+        .and_then(|upgrade| upgrade.to_str().ok())
+        .map(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false)
+}
+
+/// # `Sec-WebSocket-Accept` for the key of the browser
+fn accept_key(key: &[u8]) -> String {
+    let mut context = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+    context.update(key);
+    context.update(WEBSOCKET_GUID.as_bytes());
+    base64::encode(context.finish())
+}
+
+/// # Connect to the target with a WebSocket handshake and pass the bytes through both ways
+/// The frames are not decoded, so that extensions agreed on by the browser and the target keep working.
+async fn tunnel(proxy: &Proxy, client: &Client, req: HttpRequest, payload: web::Payload) -> HttpResponse {
+    let key = match req.headers().get(header::SEC_WEBSOCKET_KEY) {
+        Some(key) => accept_key(key.as_bytes()),
+        None => return HttpResponse::BadRequest().content_type("text/plain; charset=utf-8").body("400 Bad Request: missing Sec-WebSocket-Key"),
+    };
+    let mut request = client.ws(proxy.target_url(req.uri().path(), req.query_string()));
+    // The key, the version and the upgrade headers are set by the client.
+    for (name, value) in request_headers(proxy, &req) {
+        if name != header::SEC_WEBSOCKET_KEY && name != header::SEC_WEBSOCKET_VERSION {
+            request = request.header(name, value);
+        }
+    }
+    let (response, framed) = match request.connect().await {
+        Ok(connected) => connected,
+        Err(e) => return bad_gateway(e),
+    };
+    let mut builder = HttpResponse::SwitchingProtocols();
+    builder.upgrade("websocket");
+    for (name, value) in response_headers(proxy, response.headers()) {
+        if name != header::SEC_WEBSOCKET_ACCEPT {
+            builder.header(name, value);
+        }
+    }
+    builder.header(header::SEC_WEBSOCKET_ACCEPT, key);
+    // Bytes the target sent right after the handshake are already buffered.
+    let parts = framed.into_parts();
+    let buffered = parts.read_buf.freeze();
+    let (mut sink, stream) = Framed::new(parts.io, BytesCodec).split();
+    actix_web::rt::spawn(async move {
+        let mut payload = payload.map_err(|e| io::Error::other(e.to_string()));
+        let _ = sink.send_all(&mut payload).await;
+        let _ = sink.close().await;
+    });
+    let buffered = futures::stream::iter(Some(buffered).filter(|buffered| !buffered.is_empty()).map(Ok));
+    builder.streaming(buffered.chain(stream.map_ok(|bytes| bytes.freeze())).map_err(Error::from))
+}
+
+/// # Route of a proxy rule
+/// Each worker has its own client, which keeps the connections to the target open.
+/// Responses are streamed without a time limit, only connecting and waiting for some responses are limited.
+pub fn scope(proxy: Arc<Proxy>) -> Scope {
+    let connector = Connector::new().timeout(CONNECT_TIMEOUT).finish();
+    let client = Client::builder().connector(connector).disable_timeout().no_default_headers().finish();
+    web::scope(&proxy.path.clone()).default_service(web::route().to(move |req: HttpRequest, payload: web::Payload| {
+        let proxy = proxy.clone();
+        let client = client.clone();
+        async move {
+            Ok::<_, Error>(match websocket(&req) {
+                true => tunnel(&proxy, &client, req, payload).await,
+                false => forward(&proxy, &client, req, payload).await,
+            })
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn proxy_rules() {
+        let proxy = Proxy::parse("/api/=http://127.0.0.1:3000/").unwrap();
+        assert_eq!(proxy, Proxy{path: "/api".to_string(), target: "http://127.0.0.1:3000".to_string()});
+        assert_eq!(proxy.target_url("/api/users", "page=2"), "http://127.0.0.1:3000/api/users?page=2");
+        assert_eq!(proxy.rewrite_location("http://127.0.0.1:3000/api/login"), Some("/api/login".to_string()));
+        assert_eq!(proxy.rewrite_location("https://example.com/"), None);
+        let proxy = Proxy::parse("/api=https://backend:8443/v1").unwrap();
+        assert_eq!(proxy.target_url("/api", ""), "https://backend:8443/v1");
+        assert_eq!(proxy.target_url("/api/users", ""), "https://backend:8443/v1/users");
+        assert_eq!(proxy.rewrite_location("/v1/users?id=1"), Some("/api/users?id=1".to_string()));
+        assert_eq!(proxy.rewrite_location("https://BACKEND:8443/v1"), Some("/api".to_string()));
+        assert_eq!(proxy.rewrite_location("/v10"), None);
+        assert_eq!(accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert!(Proxy::parse("api=http://127.0.0.1:3000").is_none());
+        assert!(Proxy::parse("/api=ftp://127.0.0.1").is_none());
+        assert!(Proxy::parse("/api").is_none());
+        let req = actix_web::test::TestRequest::with_uri("/api/users")
+            .header(header::AUTHORIZATION, "Basic YWxpY2U6c2VjcmV0")
+            .header(header::COOKIE, "session=1")
+            .to_http_request();
+        let forwarded = |req: &HttpRequest, name: HeaderName| request_headers(&proxy, req).iter().any(|(forwarded, _)| *forwarded == name);
+        assert!(forwarded(&req, header::AUTHORIZATION));
+        req.extensions_mut().insert(Authenticated);
+        assert!(!forwarded(&req, header::AUTHORIZATION));
+        assert!(forwarded(&req, header::COOKIE));
+    }
+}
@@ -0,0 +1,269 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::listing;
+use super::upload;
+use super::super::Language;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{http::Method, Error, HttpMessage, HttpResponse};
+use futures::future::{ok, Either, Ready};
+use ring::hmac;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Links expire after a day by default.
+pub const DEFAULT_SHARE_EXPIRES: u64 = 24 * 60 * 60;
+
+/// # A file handed out with a signed link
+#[derive(Clone, Debug)]
+pub struct ShareLink {
+    /// Path below the distribution directory, with "/" separators.
+    pub path: String,
+    /// Unix time after which the link is refused.
+    pub expires: u64,
+    /// Number of downloads after which the link is refused.
+    /// Every GET counts, a range as much as the whole file, so resumed downloads use up the limit as well.
+    pub max_downloads: Option<u32>,
+}
+
+/// # Deliver only files requested with a signed link
+/// Links carry the expiry time, the download limit and an HMAC-SHA256 signature of both and the path.
+/// Every other request is answered with 403 Forbidden, and a valid link needs no password.
+/// The download counts are kept in memory, they start over when the server is restarted.
+#[derive(Clone)]
+pub struct Share {
+    /// Key of the signatures. Links stay valid across restarts only with the same secret.
+    pub secret: Vec<u8>,
+    /// Links shown when the server starts.
+    pub links: Vec<ShareLink>,
+}
+
+impl std::fmt::Debug for Share {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // the secret is not shown
+        f.debug_struct("Share").field("links", &self.links).finish()
+    }
+}
+
+impl Share {
+    /// # Signature of a link, in hex
+    fn signature(&self, path: &str, expires: u64, max_downloads: Option<u32>) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &self.secret);
+        let tag = hmac::sign(&key, message(path, expires, max_downloads).as_bytes());
+        tag.as_ref().iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+    }
+    /// # Path and query of the link, e.g. "/app/docs/report.pdf?expires=1626000000&sig=..."
+    pub fn link(&self, uri_prefix: &str, link: &ShareLink) -> String {
+        let encoded_path: Vec<String> = link.path.split('/').map(listing::encode_segment).collect();
+        let max_downloads = link.max_downloads.map(|max_downloads| format!("&max={}", max_downloads)).unwrap_or_default();
+        let prefix = match uri_prefix.trim_end_matches('/') {
+            "" => String::new(),
+            uri_prefix => format!("/{}", uri_prefix),
+        };
+        format!("{}/{}?expires={}{}&sig={}", prefix, encoded_path.join("/"), link.expires, max_downloads,
+            self.signature(&link.path, link.expires, link.max_downloads))
+    }
+}
+
+/// # Signed text of a link
+fn message(path: &str, expires: u64, max_downloads: Option<u32>) -> String {
+    format!("{}\n{}\n{}", path, expires, max_downloads.map(|max_downloads| max_downloads.to_string()).unwrap_or_default())
+}
+
+/// # Marks a request that came with a valid link, so that no password is asked
+pub(super) struct Shared;
+
+/// # Why a request was refused
+#[derive(Debug, PartialEq, Eq)]
+enum Refusal {
+    Unsigned,
+    Expired,
+    Exhausted,
+}
+
+/// # Middleware that lets through only requests with a valid link
+/// Requests pass through unchanged without `Share`.
+#[derive(Clone)]
+pub struct ShareLinks {
+    share: Option<Arc<Share>>,
+    uri_prefix: String,
+    /// Language of the refusals.
+    language: Language,
+    /// Downloads per signature.
+    downloads: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl ShareLinks {
+    pub fn new(share: Option<Share>, uri_prefix: String, language: Language) -> Self {
+        Self{share: share.map(Arc::new), uri_prefix, language, downloads: Arc::new(Mutex::new(HashMap::new()))}
+    }
+    fn check(&self, share: &Share, req: &ServiceRequest) -> Result<(), Refusal> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return Err(Refusal::Unsigned);
+        }
+        let tail = match self.uri_prefix.trim_end_matches('/') {
+            "" => Some(req.path().trim_start_matches('/')),
+            uri_prefix => req.path().trim_start_matches('/').strip_prefix(uri_prefix).map(|tail| tail.trim_start_matches('/')),
+        };
+        let path = tail.and_then(upload::safe_path).ok_or(Refusal::Unsigned)?;
+        let path: Vec<String> = path.components().map(|component| component.as_os_str().to_string_lossy().into_owned()).collect();
+        let path = path.join("/");
+        let (mut expires, mut max_downloads, mut signature) = (None, None, None);
+        for pair in req.query_string().split('&') {
+            match pair.split_once('=') {
+                Some(("expires", value)) => expires = value.parse::<u64>().ok(),
+                Some(("max", value)) => max_downloads = value.parse::<u32>().ok(),
+                Some(("sig", value)) => signature = Some(value),
+                _ => (),
+            }
+        }
+        let (expires, signature) = match (expires, signature) {
+            (Some(expires), Some(signature)) => (expires, signature),
+            _ => return Err(Refusal::Unsigned),
+        };
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &share.secret);
+        let tag = decode_hex(signature).ok_or(Refusal::Unsigned)?;
+        hmac::verify(&key, message(&path, expires, max_downloads).as_bytes(), &tag).map_err(|_| Refusal::Unsigned)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default();
+        if now > expires {
+            return Err(Refusal::Expired);
+        }
+        let max_downloads = match max_downloads {
+            Some(max_downloads) => max_downloads,
+            None => return Ok(()),
+        };
+        let mut downloads = self.downloads.lock().map_err(|_| Refusal::Exhausted)?;
+        let count = downloads.entry(signature.to_string()).or_insert(0);
+        if *count >= max_downloads {
+            return Err(Refusal::Exhausted);
+        }
+        // Any range may cover the whole file, so every GET counts, only HEAD requests are free.
+        if req.method() == Method::GET {
+            *count += 1;
+        }
+        Ok(())
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    // odd length, checked without `is_multiple_of`, which needs Rust 1.87
+    if hex.len() & 1 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok())).collect()
+}
+
+impl<S, B> Transform<S> for ShareLinks
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ShareLinksMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ShareLinksMiddleware{service, share_links: self.clone()})
+    }
+}
+
+pub struct ShareLinksMiddleware<S> {
+    service: S,
+    share_links: ShareLinks,
+}
+
+impl<S, B> Service for ShareLinksMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let share = match &self.share_links.share {
+            Some(share) => share.clone(),
+            None => return Either::Left(self.service.call(req)),
+        };
+        let refusal = match self.share_links.check(&share, &req) {
+            Ok(()) => {
+                req.extensions_mut().insert(Shared);
+                return Either::Left(self.service.call(req));
+            },
+            Err(refusal) => refusal,
+        };
+        let message = match (refusal, self.share_links.language) {
+            (Refusal::Unsigned, Language::Japanese) => "403 Forbidden: このサーバーは有効な共有リンクのファイルのみ配信します。",
+            (Refusal::Unsigned, Language::English) => "403 Forbidden: this server only delivers files with a valid share link",
+            (Refusal::Expired, Language::Japanese) => "403 Forbidden: 共有リンクの有効期限が切れています。",
+            (Refusal::Expired, Language::English) => "403 Forbidden: the share link has expired",
+            (Refusal::Exhausted, Language::Japanese) => "403 Forbidden: 共有リンクのダウンロード回数の上限に達しました。",
+            (Refusal::Exhausted, Language::English) => "403 Forbidden: the share link has reached its download limit",
+        };
+        let response = HttpResponse::Forbidden().content_type("text/plain; charset=utf-8").body(message);
+        Either::Right(ok(req.into_response(response).map_body(|_, body| body.into_body())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::http::header;
+
+    #[test]
+    fn signed_links() {
+        let share = Share{secret: b"secret".to_vec(), links: Vec::new()};
+        let link = ShareLink{path: "docs/a b.txt".to_string(), expires: 1626000000, max_downloads: Some(3)};
+        let url = share.link("app", &link);
+        assert!(url.starts_with("/app/docs/a%20b.txt?expires=1626000000&max=3&sig="));
+        let signature = url.rsplit("sig=").next().unwrap();
+        assert_eq!(signature.len(), 64);
+        assert_eq!(decode_hex(signature).unwrap().len(), 32);
+        // the signature covers the limit
+        assert_ne!(share.signature("docs/a b.txt", 1626000000, None), signature);
+        assert_eq!(decode_hex("0g"), None);
+        assert_eq!(decode_hex("abc"), None);
+    }
+
+    #[test]
+    fn exhausted_links() {
+        let share = Share{secret: b"secret".to_vec(), links: Vec::new()};
+        let url = share.link("", &ShareLink{path: "a.txt".to_string(), expires: u64::MAX, max_downloads: Some(2)});
+        let request = |range: Option<&str>| {
+            let request = actix_web::test::TestRequest::with_uri(&url);
+            match range {
+                Some(range) => request.header(header::RANGE, range),
+                None => request,
+            }.to_srv_request()
+        };
+        // suffix ranges, ranges that do not start at 0 and multi-ranges use up the limit like whole downloads
+        for ranges in [[Some("bytes=-10"), Some("bytes=1-")], [Some("bytes=1000-"), Some("bytes=0-0,1-")], [None, Some("bytes=0-99")]].iter() {
+            let share_links = ShareLinks::new(Some(share.clone()), String::new(), Language::English);
+            let head = actix_web::test::TestRequest::with_uri(&url).method(Method::HEAD).to_srv_request();
+            assert_eq!(share_links.check(&share, &head), Ok(()));
+            for range in ranges.iter() {
+                assert_eq!(share_links.check(&share, &request(*range)), Ok(()));
+            }
+            for range in [None, Some("bytes=0-"), Some("bytes=1-"), Some("bytes=-10")].iter() {
+                assert_eq!(share_links.check(&share, &request(*range)), Err(Refusal::Exhausted));
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use actix_files::NamedFile;
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Extensions of paths that are treated as assets by default.
+/// A missing asset is a real 404, so that a broken link to a script does not receive the index page.
+pub const DEFAULT_ASSET_EXTENSIONS: [&str; 20] = [
+    "js", "mjs", "css", "map", "json", "wasm", "txt", "xml",
+    "png", "jpg", "jpeg", "gif", "svg", "ico", "webp", "avif",
+    "woff", "woff2", "ttf", "otf",
+];
+
+/// # Single-page application mode
+/// Paths under the uri prefix that are not files receive the index file,
+/// so that the router of the application can handle deep links.
+#[derive(Clone, Debug)]
+pub struct Spa {
+    /// Index file of the application, relative to the distribution directory.
+    pub index_file: PathBuf,
+    /// Extensions (without the dot) of paths that return 404 instead of the index file.
+    pub asset_extensions: Vec<String>,
+}
+
+impl Default for Spa {
+    fn default() -> Self {
+        Self {
+            index_file: PathBuf::from("index.html"),
+            asset_extensions: DEFAULT_ASSET_EXTENSIONS.iter().map(|extension| extension.to_string()).collect(),
+        }
+    }
+}
+
+impl Spa {
+    /// # Whether the path looks like an asset
+    /// Only the last segment of the path is looked at, case-insensitively.
+    pub fn is_asset(&self, path: &str) -> bool {
+        let file_name = path.rsplit('/').next().unwrap_or_default();
+        match Path::new(file_name).extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => self.asset_extensions.iter().any(|asset| asset.eq_ignore_ascii_case(extension)),
+            None => false,
+        }
+    }
+}
+
+/// # Route for the paths that are not files
+/// Used as the default handler of `actix_files::Files`.
+pub fn fallback(distribution_dir: Arc<PathBuf>, spa: Arc<Spa>) -> actix_web::Route {
+    web::route().to(move |req: HttpRequest| {
+        let index_file = distribution_dir.join(&spa.index_file);
+        let is_asset = spa.is_asset(req.path());
+        async move {
+            if is_asset {
+                return Ok(HttpResponse::NotFound().finish());
+            }
+            NamedFile::open(index_file)?.into_response(&req)
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn asset_paths() {
+        let spa = Spa::default();
+        assert!(spa.is_asset("/app/static/main.JS"));
+        assert!(!spa.is_asset("/app/users/42"));
+        assert!(!spa.is_asset("/app/v1.2/settings"));
+        assert!(!spa.is_asset("/app/"));
+    }
+}
@@ -0,0 +1,426 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::event::{EventHandler, ServerEvent, UploadRejection};
+use super::listing;
+use super::protect::ProtectedFiles;
+use super::super::Language;
+use actix_multipart::Multipart;
+use actix_web::{error::PayloadError, guard, http::header, web, web::Bytes, HttpRequest, HttpResponse, Resource};
+use futures::{Stream, StreamExt};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Largest file accepted by default, 1 GiB.
+pub const DEFAULT_UPLOAD_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+/// Largest form post accepted by default, 4 GiB.
+pub const DEFAULT_UPLOAD_MAX_REQUEST_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Numbers the temporary files of the uploads in progress.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// # What to do when an uploaded file already exists
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Refuse the upload with 409 Conflict.
+    Reject,
+    /// Save it as "name (1).ext", "name (2).ext", ...
+    Rename,
+    /// Replace the existing file.
+    Replace,
+}
+
+/// # Accept files into the distribution directory
+/// Files are sent with `PUT /path/to/file`, or posted as `multipart/form-data` to a directory,
+/// which is what the form of the listing does.
+/// They are written to a hidden temporary file next to the target and renamed when complete,
+/// so a partly received file is never delivered.
+#[derive(Clone, Debug)]
+pub struct Upload {
+    /// Largest file accepted, in bytes.
+    pub max_size: u64,
+    /// Largest form post accepted, in bytes, counting every part of the body.
+    pub max_request_size: u64,
+    pub overwrite: Overwrite,
+}
+
+impl Default for Upload {
+    fn default() -> Self {
+        Self{max_size: DEFAULT_UPLOAD_MAX_SIZE, max_request_size: DEFAULT_UPLOAD_MAX_REQUEST_SIZE, overwrite: Overwrite::Reject}
+    }
+}
+
+/// # What the upload handlers need from the server settings
+pub struct UploadSettings {
+    pub distribution_dir: PathBuf,
+    pub uri_prefix: String,
+    pub upload: Upload,
+    /// Language of the error messages.
+    pub language: Language,
+    /// Never replaced by an upload.
+    pub protected_files: ProtectedFiles,
+    pub on_event: Option<EventHandler>,
+}
+
+impl UploadSettings {
+    fn emit(&self, event: ServerEvent) {
+        if let Some(on_event) = &self.on_event {
+            on_event(event);
+        }
+    }
+    /// # Report the refused upload and answer with its status
+    fn reject(&self, path: PathBuf, reason: UploadRejection, client: Option<String>) -> HttpResponse {
+        self.emit(ServerEvent::UploadRejected{path, reason, client});
+        let message = match (reason, self.language) {
+            (UploadRejection::TooLarge, Language::Japanese) => "ファイルが大きすぎます。",
+            (UploadRejection::TooLarge, Language::English) => "The file is too large.",
+            (UploadRejection::Exists, Language::Japanese) => "同じ名前のファイルが既にあります。",
+            (UploadRejection::Exists, Language::English) => "A file with the same name already exists.",
+            (UploadRejection::InvalidPath, Language::Japanese) => "この場所にはアップロードできません。",
+            (UploadRejection::InvalidPath, Language::English) => "Files can not be uploaded to this path.",
+            (UploadRejection::Io, Language::Japanese) => "ファイルを保存できませんでした。",
+            (UploadRejection::Io, Language::English) => "The file could not be saved.",
+        };
+        match reason {
+            UploadRejection::TooLarge => HttpResponse::PayloadTooLarge(),
+            UploadRejection::Exists => HttpResponse::Conflict(),
+            UploadRejection::InvalidPath => HttpResponse::BadRequest(),
+            UploadRejection::Io => HttpResponse::InternalServerError(),
+        }.content_type("text/plain; charset=utf-8").body(message)
+    }
+    /// # Path of the request below the uri prefix, still percent-encoded
+    pub(super) fn tail<'a>(&self, request_path: &'a str) -> &'a str {
+        let request_path = request_path.trim_start_matches('/');
+        match self.uri_prefix.trim_end_matches('/') {
+            "" => request_path,
+            uri_prefix => request_path.strip_prefix(uri_prefix).unwrap_or_default().trim_start_matches('/'),
+        }
+    }
+    /// # Link to a file below the distribution directory
+    pub(super) fn url(&self, relative: &Path) -> String {
+        let segments: Vec<String> = relative.components()
+            .map(|component| listing::encode_segment(&component.as_os_str().to_string_lossy()))
+            .collect();
+        match self.uri_prefix.trim_end_matches('/') {
+            "" => format!("/{}", segments.join("/")),
+            uri_prefix => format!("/{}/{}", uri_prefix, segments.join("/")),
+        }
+    }
+}
+
+/// # A file written by an upload
+struct Saved {
+    /// Relative to the distribution directory, after renaming.
+    path: PathBuf,
+    size: u64,
+    replaced: bool,
+}
+
+/// # Pattern of the resources that handle every path under the uri prefix
+/// The uri prefix itself matches with or without the trailing slash.
+pub(super) fn resource_pattern(uri_prefix: &str) -> String {
+    match uri_prefix.trim_end_matches('/') {
+        "" => "/{tail:.*}".to_string(),
+        uri_prefix => format!("/{}{{tail:(/.*)?}}", uri_prefix),
+    }
+}
+
+/// # Routes for PUT and POST under the uri prefix
+/// Registered before the files service, which answers the other methods.
+pub fn resource(settings: Arc<UploadSettings>) -> Resource {
+    let put_settings = settings.clone();
+    web::resource(resource_pattern(&settings.uri_prefix))
+        .guard(guard::Any(guard::Put()).or(guard::Post()))
+        .route(web::put().to(move |req: HttpRequest, payload: web::Payload| put(put_settings.clone(), req, payload)))
+        .route(web::post().to(move |req: HttpRequest, payload: web::Payload| post(settings.clone(), req, payload)))
+}
+
+/// # Decode the path of a request into a path below the distribution directory
+/// None if a segment is hidden or could leave the directory.
+pub(super) fn safe_path(tail: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for segment in tail.split('/') {
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+        match segment.as_ref() {
+            "" => continue,
+            segment if segment.starts_with('.') || segment.contains('\\') || segment.contains('\0') => return None,
+            segment => path.push(segment),
+        }
+    }
+    // e.g. "C:" on Windows
+    match path.components().all(|component| matches!(component, Component::Normal(_))) {
+        true => Some(path),
+        false => None,
+    }
+}
+
+/// # Whether the parent directories of a path below the distribution directory stay inside it
+/// Directories that are symbolic links are resolved, so a link can not lead a write outside.
+/// Directories that do not exist yet are checked by their nearest existing ancestor.
+pub(super) fn parent_inside(distribution_dir: &Path, relative: &Path) -> bool {
+    let canonical_dir = match distribution_dir.canonicalize() {
+        Ok(canonical_dir) => canonical_dir,
+        Err(_) => return false,
+    };
+    let mut existing = distribution_dir.join(relative);
+    while existing.pop() {
+        if let Ok(resolved) = existing.canonicalize() {
+            return resolved.starts_with(&canonical_dir);
+        }
+    }
+    false
+}
+
+/// # Address of the client, for the events
+pub(super) fn client(req: &HttpRequest) -> Option<String> {
+    req.connection_info().realip_remote_addr().map(String::from)
+}
+
+/// # Size of the body announced by the client
+fn content_length(req: &HttpRequest) -> Option<u64> {
+    req.headers().get(header::CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok())
+        .and_then(|content_length| content_length.parse::<u64>().ok())
+}
+
+/// # Save the body of a PUT request as the file of the path
+/// 201 Created for a new file, 204 No Content if it was replaced.
+pub(super) async fn put(settings: Arc<UploadSettings>, req: HttpRequest, payload: web::Payload) -> HttpResponse {
+    let client = client(&req);
+    let tail = settings.tail(req.path());
+    let relative = match safe_path(tail) {
+        Some(relative) if !tail.ends_with('/') && relative.file_name().is_some() => relative,
+        _ => return settings.reject(PathBuf::from(percent_decode_str(tail).decode_utf8_lossy().as_ref()), UploadRejection::InvalidPath, client),
+    };
+    if matches!(content_length(&req), Some(content_length) if content_length > settings.upload.max_size) {
+        return settings.reject(relative, UploadRejection::TooLarge, client);
+    }
+    match receive(&settings, payload, relative.clone(), client.clone()).await {
+        Ok(saved) => match saved.replaced {
+            true => HttpResponse::NoContent().finish(),
+            false => HttpResponse::Created().header(header::LOCATION, settings.url(&saved.path)).finish(),
+        },
+        Err(reason) => settings.reject(relative, reason, client),
+    }
+}
+
+/// # Saved file in the answer to a form post
+#[derive(Serialize)]
+struct JsonSaved {
+    name: String,
+    size: u64,
+    url: String,
+}
+
+/// # Save the files of a `multipart/form-data` post into the directory of the path
+/// Browsers are sent back to the listing with 303 See Other, other clients receive the saved files as JSON.
+/// The first refused file ends the request, the files before it are kept.
+/// The whole body, with the fields that are not files, is limited by `Upload::max_request_size`.
+async fn post(settings: Arc<UploadSettings>, req: HttpRequest, payload: web::Payload) -> HttpResponse {
+    let client = client(&req);
+    let tail = settings.tail(req.path());
+    let dir = match safe_path(tail) {
+        Some(dir) if settings.distribution_dir.join(&dir).is_dir() => dir,
+        _ => return settings.reject(PathBuf::from(percent_decode_str(tail).decode_utf8_lossy().as_ref()), UploadRejection::InvalidPath, client),
+    };
+    let max_request_size = settings.upload.max_request_size;
+    if matches!(content_length(&req), Some(content_length) if content_length > max_request_size) {
+        return settings.reject(dir, UploadRejection::TooLarge, client);
+    }
+    // Counted before the body is split into fields, so a chunked body is stopped as well.
+    let received = Rc::new(Cell::new(0u64));
+    let counter = received.clone();
+    let payload = payload.map(move |chunk| chunk.and_then(|chunk| {
+        counter.set(counter.get() + chunk.len() as u64);
+        match counter.get() > max_request_size {
+            true => Err(PayloadError::Overflow),
+            false => Ok(chunk),
+        }
+    }));
+    let too_large = |reason| match received.get() > max_request_size {
+        true => UploadRejection::TooLarge,
+        false => reason,
+    };
+    let mut multipart = Multipart::new(req.headers(), payload);
+    let mut saved_files = Vec::new();
+    while let Some(field) = multipart.next().await {
+        let field = match field {
+            Ok(field) => field,
+            Err(_) => return settings.reject(dir, too_large(UploadRejection::Io), client),
+        };
+        // Fields other than files are ignored, as are empty file inputs.
+        let file_name = match field.content_disposition().and_then(|disposition| disposition.get_filename().map(String::from)) {
+            Some(file_name) if !file_name.is_empty() => file_name,
+            _ => continue,
+        };
+        // Some browsers send the path on the client, only the name is used.
+        let file_name = file_name.rsplit(['/', '\\']).next().unwrap_or_default().to_string();
+        if file_name.is_empty() || file_name.starts_with('.') {
+            return settings.reject(dir.join(file_name), UploadRejection::InvalidPath, client);
+        }
+        match receive(&settings, field, dir.join(&file_name), client.clone()).await {
+            Ok(saved) => saved_files.push(saved),
+            Err(reason) => return settings.reject(dir.join(file_name), too_large(reason), client),
+        }
+    }
+    let accepts_html = req.headers().get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains("text/html"))
+        .unwrap_or(false);
+    match accepts_html {
+        true => HttpResponse::SeeOther().header(header::LOCATION, listing::dir_url(req.path())).finish(),
+        false => {
+            let saved_files: Vec<JsonSaved> = saved_files.iter().map(|saved| JsonSaved {
+                name: saved.path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+                size: saved.size,
+                url: settings.url(&saved.path),
+            }).collect();
+            HttpResponse::Created().content_type("application/json").body(serde_json::to_string(&saved_files).unwrap_or_default())
+        },
+    }
+}
+
+/// # Write a received file into place and report it
+/// `relative` is the requested path below the distribution directory.
+async fn receive<S, E>(settings: &UploadSettings, stream: S, relative: PathBuf, client: Option<String>) -> Result<Saved, UploadRejection>
+    where S: Stream<Item = Result<Bytes, E>> + Unpin {
+    let target = settings.distribution_dir.join(&relative);
+    if target.is_dir() || settings.protected_files.contains(&target) || !parent_inside(&settings.distribution_dir, &relative) {
+        return Err(UploadRejection::InvalidPath);
+    }
+    // Checked before receiving, and again when the file is moved into place.
+    if settings.upload.overwrite == Overwrite::Reject && target.exists() {
+        return Err(UploadRejection::Exists);
+    }
+    let parent = target.parent().map(Path::to_path_buf).unwrap_or_else(|| settings.distribution_dir.clone());
+    fs::create_dir_all(&parent).map_err(|_| UploadRejection::Io)?;
+    // A directory may have been replaced by a link in the meantime.
+    if !parent_inside(&settings.distribution_dir, &relative) {
+        return Err(UploadRejection::InvalidPath);
+    }
+    let temp = parent.join(format!(".{}.{}-{}.upload",
+        relative.file_name().unwrap_or_default().to_string_lossy(), std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let file = File::create(&temp).map_err(|_| UploadRejection::Io)?;
+    let finalized = match write(stream, file, settings.upload.max_size).await {
+        Ok(size) => finalize(&temp, &target, settings.upload.overwrite)
+            .map_err(|_| UploadRejection::Io)
+            .and_then(|finalized| finalized.ok_or(UploadRejection::Exists))
+            .map(|(path, replaced)| (path, size, replaced)),
+        Err(reason) => Err(reason),
+    };
+    let (path, size, replaced) = match finalized {
+        Ok(finalized) => finalized,
+        Err(reason) => {
+            let _ = fs::remove_file(&temp);
+            return Err(reason);
+        },
+    };
+    let path = path.strip_prefix(&settings.distribution_dir).map(Path::to_path_buf).unwrap_or(path);
+    settings.emit(ServerEvent::Uploaded{path: path.clone(), size, replaced, client});
+    Ok(Saved{path, size, replaced})
+}
+
+/// # Write the stream to the file, up to `max_size` bytes
+async fn write<S, E>(mut stream: S, mut file: File, max_size: u64) -> Result<u64, UploadRejection>
+    where S: Stream<Item = Result<Bytes, E>> + Unpin {
+    let mut size = 0u64;
+    while let Some(chunk) = stream.next().await {
+        // e.g. the client closed the connection
+        let chunk = chunk.map_err(|_| UploadRejection::Io)?;
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err(UploadRejection::TooLarge);
+        }
+        file = web::block(move || file.write_all(&chunk).map(|_| file)).await.map_err(|_| UploadRejection::Io)?;
+    }
+    web::block(move || file.sync_all()).await.map_err(|_| UploadRejection::Io)?;
+    Ok(size)
+}
+
+/// # Move the temporary file into place according to the overwrite policy
+/// Returns the final path and whether a file was replaced, or None if the file exists and may not be replaced.
+fn finalize(temp: &Path, target: &Path, overwrite: Overwrite) -> io::Result<Option<(PathBuf, bool)>> {
+    match overwrite {
+        Overwrite::Replace => {
+            let replaced = target.exists();
+            fs::rename(temp, target)?;
+            Ok(Some((target.to_path_buf(), replaced)))
+        },
+        Overwrite::Reject => match move_new(temp, target)? {
+            true => Ok(Some((target.to_path_buf(), false))),
+            false => Ok(None),
+        },
+        Overwrite::Rename => {
+            let mut number = 0;
+            loop {
+                let candidate = match number {
+                    0 => target.to_path_buf(),
+                    number => numbered(target, number),
+                };
+                if move_new(temp, &candidate)? {
+                    return Ok(Some((candidate, false)));
+                }
+                number += 1;
+            }
+        },
+    }
+}
+
+/// # Move the file to `target` unless something is there
+/// Creating a hard link fails if the name is taken, so two uploads can not take the same name.
+/// File systems without hard links fall back to a check and a rename.
+fn move_new(temp: &Path, target: &Path) -> io::Result<bool> {
+    match fs::hard_link(temp, target) {
+        Ok(()) => fs::remove_file(temp).map(|_| true),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(_) if target.symlink_metadata().is_ok() => Ok(false),
+        Err(_) => fs::rename(temp, target).map(|_| true),
+    }
+}
+
+/// # "name (1).ext" for "name.ext"
+fn numbered(target: &Path, number: u32) -> PathBuf {
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match target.extension() {
+        Some(extension) => format!("{} ({}).{}", stem, number, extension.to_string_lossy()),
+        None => format!("{} ({})", stem, number),
+    };
+    target.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn upload_paths() {
+        assert_eq!(safe_path("docs/a%20b.txt"), Some(PathBuf::from("docs/a b.txt")));
+        assert_eq!(safe_path(""), Some(PathBuf::new()));
+        assert_eq!(safe_path("docs/../../etc/passwd"), None);
+        assert_eq!(safe_path("%2e%2e/secret"), None);
+        assert_eq!(safe_path(".git/config"), None);
+        assert_eq!(safe_path("a%5Cb"), None);
+        assert_eq!(numbered(Path::new("dir/report.pdf"), 2), PathBuf::from("dir/report (2).pdf"));
+        assert_eq!(numbered(Path::new("README"), 1), PathBuf::from("README (1)"));
+        #[cfg(unix)]
+        {
+            let dir = std::env::temp_dir().join(format!("happy_server_upload_{}_{:?}", std::process::id(), std::thread::current().id()));
+            fs::create_dir_all(dir.join("dist/docs")).unwrap();
+            fs::create_dir_all(dir.join("outside")).unwrap();
+            std::os::unix::fs::symlink(dir.join("outside"), dir.join("dist/link")).unwrap();
+            let distribution_dir = dir.join("dist");
+            assert!(parent_inside(&distribution_dir, Path::new("docs/new/a.txt")));
+            assert!(parent_inside(&distribution_dir, Path::new("link")));
+            assert!(!parent_inside(&distribution_dir, Path::new("link/a.txt")));
+            assert!(!parent_inside(&distribution_dir, Path::new("link/new/a.txt")));
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
@@ -0,0 +1,364 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use super::listing::format_rfc3339;
+use super::upload::{self, UploadSettings};
+use actix_web::{guard, http::header, http::header::HttpDate, http::StatusCode, web, HttpRequest, HttpResponse, Resource};
+use std::fmt::Write;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+/// Methods answered in every WebDAV mode.
+const READ_METHODS: [&str; 2] = ["OPTIONS", "PROPFIND"];
+/// Methods that change the distribution directory.
+const WRITE_METHODS: [&str; 8] = ["PUT", "DELETE", "MKCOL", "COPY", "MOVE", "PROPPATCH", "LOCK", "UNLOCK"];
+
+/// # Let file managers mount the distribution directory
+/// GET and HEAD are answered by the files service as usual, this adds the WebDAV methods.
+/// Without `writable`, the methods that change files return 403 Forbidden.
+/// Locks are not kept, LOCK answers with a token so that clients which require locking can write.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WebDav {
+    pub writable: bool,
+}
+
+/// # What the WebDAV handlers need from the server settings
+/// Files are written with PUT like uploads, so the upload settings are shared.
+pub struct WebDavSettings {
+    pub webdav: WebDav,
+    pub upload: Arc<UploadSettings>,
+    /// Whether directory contents may be listed, like the listing of the files service.
+    pub listing: bool,
+}
+
+/// # Route for the WebDAV methods under the uri prefix
+/// Registered before the files service, which answers GET and HEAD.
+pub fn resource(settings: Arc<WebDavSettings>) -> Resource {
+    web::resource(upload::resource_pattern(&settings.upload.uri_prefix))
+        .guard(guard::fn_guard(|head| {
+            let method = head.method.as_str();
+            READ_METHODS.contains(&method) || WRITE_METHODS.contains(&method)
+        }))
+        .route(web::route().to(move |req: HttpRequest, payload: web::Payload| handle(settings.clone(), req, payload)))
+}
+
+async fn handle(settings: Arc<WebDavSettings>, req: HttpRequest, payload: web::Payload) -> HttpResponse {
+    let tail = settings.upload.tail(req.path());
+    let relative = match upload::safe_path(tail) {
+        Some(relative) => relative,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    let is_write = WRITE_METHODS.contains(&req.method().as_str());
+    if is_write && (!settings.webdav.writable || !upload::parent_inside(&settings.upload.distribution_dir, &relative)) {
+        return HttpResponse::Forbidden().finish();
+    }
+    match req.method().as_str() {
+        "OPTIONS" => options(&settings),
+        "PROPFIND" => propfind(&settings, &req, &relative),
+        "PUT" => upload::put(settings.upload.clone(), req, payload).await,
+        "DELETE" => delete(&settings, relative).await,
+        "MKCOL" => mkcol(&settings, &relative),
+        "COPY" => copy_or_move(&settings, &req, relative, false).await,
+        "MOVE" => copy_or_move(&settings, &req, relative, true).await,
+        "PROPPATCH" => proppatch(&settings, &relative),
+        "LOCK" => lock(&settings, &relative),
+        "UNLOCK" => HttpResponse::NoContent().finish(),
+        _ => HttpResponse::MethodNotAllowed().finish(),
+    }
+}
+
+/// # Tell the client which methods are supported
+/// Class 2 (locking) is announced only if writes are enabled, so read-only mounts are not offered writes.
+fn options(settings: &WebDavSettings) -> HttpResponse {
+    let (dav, allow) = match settings.webdav.writable {
+        true => ("1, 2", format!("GET, HEAD, {}, {}", READ_METHODS.join(", "), WRITE_METHODS.join(", "))),
+        false => ("1", format!("GET, HEAD, {}", READ_METHODS.join(", "))),
+    };
+    HttpResponse::Ok()
+        .header("DAV", dav)
+        .header(header::ALLOW, allow)
+        .header("MS-Author-Via", "DAV")
+        .finish()
+}
+
+/// # Describe a file or a directory and, with `Depth: 1`, its contents
+/// `Depth: infinity` is refused with 403 Forbidden as allowed by RFC 4918, clients walk the tree themselves.
+/// A request without `Depth` is taken as `Depth: 1` for the same reason.
+/// Without the listing, the contents of a directory are refused with 403 Forbidden as well, only `Depth: 0` is answered.
+fn propfind(settings: &WebDavSettings, req: &HttpRequest, relative: &Path) -> HttpResponse {
+    let path = settings.upload.distribution_dir.join(relative);
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    let depth = req.headers().get("Depth").map(|depth| depth.as_bytes().to_ascii_lowercase());
+    if depth.as_deref() == Some(b"infinity") {
+        return HttpResponse::Forbidden().content_type("application/xml; charset=utf-8")
+            .body("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n");
+    }
+    let depth_zero = depth.as_deref() == Some(b"0");
+    if metadata.is_dir() && !depth_zero && !settings.listing {
+        return HttpResponse::Forbidden().finish();
+    }
+    let mut body = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    response_xml(settings, &mut body, relative, &metadata);
+    if metadata.is_dir() && !depth_zero {
+        let mut children: Vec<_> = match fs::read_dir(&path) {
+            Ok(read_dir) => read_dir.filter_map(Result::ok)
+                .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+                .filter(|entry| !settings.upload.protected_files.contains(&entry.path()))
+                .collect(),
+            Err(_) => return HttpResponse::Forbidden().finish(),
+        };
+        children.sort_by_key(|entry| entry.file_name());
+        for child in children {
+            // follows symbolic links, broken ones are left out
+            if let Ok(child_metadata) = fs::metadata(child.path()) {
+                response_xml(settings, &mut body, &relative.join(child.file_name()), &child_metadata);
+            }
+        }
+    }
+    body.push_str("</D:multistatus>\n");
+    HttpResponse::build(StatusCode::MULTI_STATUS).content_type("application/xml; charset=utf-8").body(body)
+}
+
+/// # `<D:response>` with the properties of one file or directory
+fn response_xml(settings: &WebDavSettings, body: &mut String, relative: &Path, metadata: &Metadata) {
+    let is_dir = metadata.is_dir();
+    let href = match (is_dir, settings.upload.url(relative)) {
+        (true, url) if !url.ends_with('/') => format!("{}/", url),
+        (_, url) => url,
+    };
+    let display_name = relative.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let mtime = modified.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default();
+    let created = metadata.created().ok()
+        .and_then(|created| created.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or(mtime);
+    let _ = write!(body, "<D:response><D:href>{href}</D:href><D:propstat><D:prop>\
+        <D:displayname>{name}</D:displayname>\
+        <D:creationdate>{created}</D:creationdate>\
+        <D:getlastmodified>{modified}</D:getlastmodified>",
+        href = escape(&href), name = escape(&display_name), created = format_rfc3339(created), modified = HttpDate::from(modified));
+    match is_dir {
+        true => body.push_str("<D:resourcetype><D:collection/></D:resourcetype>"),
+        false => {
+            let _ = write!(body, "<D:resourcetype/><D:getcontentlength>{size}</D:getcontentlength>\
+                <D:getcontenttype>{mime}</D:getcontenttype><D:getetag>\"{mtime:x}-{size:x}\"</D:getetag>",
+                size = metadata.len(), mime = escape(mime_guess::from_path(relative).first_or_octet_stream().as_ref()), mtime = mtime);
+        },
+    }
+    if settings.webdav.writable {
+        body.push_str("<D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>");
+    }
+    body.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+}
+
+/// # Delete a file or a directory with its contents
+async fn delete(settings: &WebDavSettings, relative: PathBuf) -> HttpResponse {
+    if relative.as_os_str().is_empty() {
+        return HttpResponse::Forbidden().finish();
+    }
+    let path = settings.upload.distribution_dir.join(relative);
+    let is_dir = match fs::symlink_metadata(&path) {
+        Ok(metadata) => metadata.is_dir(),
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    let result = web::block(move || match is_dir {
+        true => fs::remove_dir_all(&path),
+        false => fs::remove_file(&path),
+    }).await;
+    match result {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// # Create a directory
+/// The parent has to exist, as required by RFC 4918.
+fn mkcol(settings: &WebDavSettings, relative: &Path) -> HttpResponse {
+    match fs::create_dir(settings.upload.distribution_dir.join(relative)) {
+        Ok(()) => HttpResponse::Created().finish(),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => HttpResponse::MethodNotAllowed().finish(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => HttpResponse::Conflict().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// # Copy or move to the path in the `Destination` header
+/// An existing destination is replaced unless `Overwrite: F` is sent.
+async fn copy_or_move(settings: &WebDavSettings, req: &HttpRequest, relative: PathBuf, is_move: bool) -> HttpResponse {
+    let destination = match destination(settings, req) {
+        Some(destination) => destination,
+        None => return HttpResponse::BadRequest().finish(),
+    };
+    // the root can not be moved, a directory can not be put inside itself, and nothing is put behind a link out of the tree
+    if relative.as_os_str().is_empty() || destination.as_os_str().is_empty() || destination.starts_with(&relative)
+        || !upload::parent_inside(&settings.upload.distribution_dir, &destination) {
+        return HttpResponse::Forbidden().finish();
+    }
+    let source = settings.upload.distribution_dir.join(relative);
+    let target = settings.upload.distribution_dir.join(destination);
+    if fs::symlink_metadata(&source).is_err() {
+        return HttpResponse::NotFound().finish();
+    }
+    if !target.parent().map(Path::is_dir).unwrap_or(false) {
+        return HttpResponse::Conflict().finish();
+    }
+    let overwrite = req.headers().get("Overwrite").map(|overwrite| overwrite.as_bytes() != b"F").unwrap_or(true);
+    let existed = fs::symlink_metadata(&target).is_ok();
+    if existed && !overwrite {
+        return HttpResponse::PreconditionFailed().finish();
+    }
+    let result = web::block(move || {
+        if existed {
+            match fs::symlink_metadata(&target)?.is_dir() {
+                true => fs::remove_dir_all(&target)?,
+                false => fs::remove_file(&target)?,
+            }
+        }
+        match is_move {
+            true => fs::rename(&source, &target),
+            false => copy_recursive(&source, &target),
+        }
+    }).await;
+    match (result, existed) {
+        (Ok(()), true) => HttpResponse::NoContent().finish(),
+        (Ok(()), false) => HttpResponse::Created().finish(),
+        (Err(_), _) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// # Path below the distribution directory of the `Destination` header
+/// The header is a full URL or an absolute path, and has to be under the uri prefix.
+fn destination(settings: &WebDavSettings, req: &HttpRequest) -> Option<PathBuf> {
+    let destination = req.headers().get("Destination")?.to_str().ok()?;
+    let path = match destination.find("://") {
+        Some(scheme_end) => {
+            let authority_and_path = &destination[scheme_end + 3..];
+            &authority_and_path[authority_and_path.find('/')?..]
+        },
+        None => destination,
+    };
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let uri_prefix = settings.upload.uri_prefix.trim_end_matches('/');
+    let below_prefix = path.trim_start_matches('/').strip_prefix(uri_prefix)
+        .map(|rest| uri_prefix.is_empty() || rest.is_empty() || rest.starts_with('/'))
+        .unwrap_or(false);
+    match below_prefix {
+        true => upload::safe_path(settings.upload.tail(path)),
+        false => None,
+    }
+}
+
+/// # Copy a file, or a directory with its contents
+/// Symbolic links are copied as links and not followed, so a copy neither takes in files from outside the tree nor loops.
+/// Where links can not be created, they are left out.
+fn copy_recursive(source: &Path, target: &Path) -> io::Result<()> {
+    let file_type = fs::symlink_metadata(source)?.file_type();
+    if file_type.is_symlink() {
+        return copy_link(source, target);
+    }
+    match file_type.is_dir() {
+        true => {
+            fs::create_dir(target)?;
+            for entry in fs::read_dir(source)? {
+                let entry = entry?;
+                copy_recursive(&entry.path(), &target.join(entry.file_name()))?;
+            }
+            Ok(())
+        },
+        false => fs::copy(source, target).map(|_| ()),
+    }
+}
+
+#[cfg(unix)]
+fn copy_link(source: &Path, target: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(source)?, target)
+}
+
+#[cfg(not(unix))]
+fn copy_link(_source: &Path, _target: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// # Accept property changes without storing them
+/// Windows sets the file times after writing a file and fails the copy if this is refused.
+fn proppatch(settings: &WebDavSettings, relative: &Path) -> HttpResponse {
+    if fs::metadata(settings.upload.distribution_dir.join(relative)).is_err() {
+        return HttpResponse::NotFound().finish();
+    }
+    let body = format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n\
+        <D:response><D:href>{}</D:href><D:propstat><D:prop/><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n\
+        </D:multistatus>\n", escape(&settings.upload.url(relative)));
+    HttpResponse::build(StatusCode::MULTI_STATUS).content_type("application/xml; charset=utf-8").body(body)
+}
+
+/// # Escape text for XML
+/// Unlike HTML escaping, "/" is kept so that the hrefs stay readable for clients.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// # Hand out a lock token without locking anything
+fn lock(settings: &WebDavSettings, relative: &Path) -> HttpResponse {
+    let token = format!("opaquelocktoken:{:x}-{:x}", std::process::id(),
+        std::time::SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos()).unwrap_or_default());
+    let body = format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery><D:activelock>\
+        <D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope><D:depth>infinity</D:depth>\
+        <D:timeout>Second-3600</D:timeout><D:locktoken><D:href>{token}</D:href></D:locktoken>\
+        <D:lockroot><D:href>{href}</D:href></D:lockroot></D:activelock></D:lockdiscovery></D:prop>\n",
+        token = token, href = escape(&settings.upload.url(relative)));
+    HttpResponse::Ok()
+        .header("Lock-Token", format!("<{}>", token))
+        .content_type("application/xml; charset=utf-8")
+        .body(body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::protect::ProtectedFiles;
+    use super::super::upload::Upload;
+    use super::super::super::Language;
+    use actix_web::{test, App};
+
+    #[test]
+    fn propfind_without_listing() {
+        let dir = std::env::temp_dir().join(format!("happy_server_webdav_{}_{:?}", std::process::id(), std::thread::current().id()));
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::write(dir.join("docs/a.txt"), "a").unwrap();
+        let distribution_dir = dir.clone();
+        actix_web::rt::System::new("propfind_without_listing").block_on(async move {
+            for (listing, depth, path, status) in [
+                (false, Some("1"), "/docs/", 403),
+                (false, None, "/docs/", 403),
+                (false, Some("0"), "/docs/", 207),
+                (false, Some("1"), "/docs/a.txt", 207),
+                (true, Some("1"), "/docs/", 207),
+            ].iter() {
+                let upload = Arc::new(UploadSettings{
+                    distribution_dir: distribution_dir.clone(),
+                    uri_prefix: String::new(),
+                    upload: Upload::default(),
+                    language: Language::English,
+                    protected_files: ProtectedFiles::default(),
+                    on_event: None,
+                });
+                let settings = Arc::new(WebDavSettings{webdav: WebDav::default(), upload, listing: *listing});
+                let mut app = test::init_service(App::new().service(resource(settings))).await;
+                let mut request = test::TestRequest::with_uri(path).method("PROPFIND".parse().unwrap());
+                if let Some(depth) = depth {
+                    request = request.header("Depth", *depth);
+                }
+                let response = test::call_service(&mut app, request.to_request()).await;
+                assert_eq!(response.status().as_u16(), *status, "listing {} depth {:?} {}", listing, depth, path);
+            }
+        });
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use flate2::{write::DeflateEncoder, Compression, Crc};
+use std::io::{self, Read, Write};
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP64_END: u32 = 0x06064b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+const END: u32 = 0x06054b50;
+/// Sizes are in the data descriptor, names are UTF-8.
+const FLAGS: u16 = 0x0808;
+/// Files that may not fit in 32 bits after compression are written as ZIP64 entries.
+/// Deflate adds up to 5 bytes per 64 KiB to data that does not compress, which the margin covers.
+const ZIP64_THRESHOLD: u64 = 0xFFF0_0000;
+
+/// # Zip writer that does not seek
+/// Sizes and checksums are written after the data (data descriptors),
+/// so the archive can be sent while it is written.
+pub struct ZipStream<W: Write> {
+    writer: CountingWriter<W>,
+    entries: Vec<CentralEntry>,
+}
+
+struct CentralEntry {
+    name: String,
+    method: u16,
+    dos_time: (u16, u16),
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    offset: u64,
+    mode: u32,
+    zip64: bool,
+}
+
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> ZipStream<W> {
+    pub fn new(writer: W) -> Self {
+        Self{writer: CountingWriter{inner: writer, count: 0}, entries: Vec::new()}
+    }
+    /// # Add a directory, `name` ends with "/"
+    pub fn add_dir(&mut self, name: &str, dos_time: (u16, u16), mode: u32) -> io::Result<()> {
+        let offset = self.writer.count;
+        self.local_header(name, 0, dos_time, false)?;
+        self.data_descriptor(0, 0, 0, false)?;
+        self.entries.push(CentralEntry{name: name.to_string(), method: 0, dos_time, crc: 0, compressed_size: 0, size: 0, offset, mode: mode | 0o040000, zip64: false});
+        Ok(())
+    }
+    /// # Add a file, deflated
+    /// `size_hint` is the size from the metadata, used to decide on ZIP64 before the data is written.
+    pub fn add_file(&mut self, name: &str, reader: &mut impl Read, size_hint: u64, dos_time: (u16, u16), mode: u32) -> io::Result<()> {
+        let offset = self.writer.count;
+        let zip64 = size_hint >= ZIP64_THRESHOLD;
+        self.local_header(name, 8, dos_time, zip64)?;
+        let mut crc = Crc::new();
+        let start = self.writer.count;
+        let mut encoder = DeflateEncoder::new(&mut self.writer, Compression::default());
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            crc.update(&buffer[..read]);
+            encoder.write_all(&buffer[..read])?;
+        }
+        encoder.finish()?;
+        let compressed_size = self.writer.count - start;
+        let size = crc.amount() as u64;
+        if !zip64 && (size >= u32::MAX as u64 || compressed_size >= u32::MAX as u64) {
+            // The file grew after the header was written, the archive can not describe it.
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "file grew beyond 4 GiB while archiving"));
+        }
+        self.data_descriptor(crc.sum(), compressed_size, size, zip64)?;
+        self.entries.push(CentralEntry{name: name.to_string(), method: 8, dos_time, crc: crc.sum(), compressed_size, size, offset, mode: mode | 0o100000, zip64});
+        Ok(())
+    }
+    /// # Write the central directory and return the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let central_start = self.writer.count;
+        for entry in &self.entries {
+            // ZIP64 extra field: only the values that do not fit in the header, in this order.
+            let mut extra = Vec::new();
+            let size = if entry.zip64 || entry.size >= u32::MAX as u64 {
+                extra.extend_from_slice(&entry.size.to_le_bytes());
+                u32::MAX
+            } else {entry.size as u32};
+            let compressed_size = if entry.zip64 || entry.compressed_size >= u32::MAX as u64 {
+                extra.extend_from_slice(&entry.compressed_size.to_le_bytes());
+                u32::MAX
+            } else {entry.compressed_size as u32};
+            let offset = if entry.offset >= u32::MAX as u64 {
+                extra.extend_from_slice(&entry.offset.to_le_bytes());
+                u32::MAX
+            } else {entry.offset as u32};
+            let extra = match extra.is_empty() {
+                true => extra,
+                false => [&1u16.to_le_bytes()[..], &(extra.len() as u16).to_le_bytes()[..], &extra[..]].concat(),
+            };
+            let version = if extra.is_empty() {20} else {45};
+            let mut header = Vec::with_capacity(46 + entry.name.len() + extra.len());
+            header.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            header.extend_from_slice(&(0x0300u16 | version).to_le_bytes()); // made by unix
+            header.extend_from_slice(&version.to_le_bytes());
+            header.extend_from_slice(&FLAGS.to_le_bytes());
+            header.extend_from_slice(&entry.method.to_le_bytes());
+            header.extend_from_slice(&entry.dos_time.0.to_le_bytes());
+            header.extend_from_slice(&entry.dos_time.1.to_le_bytes());
+            header.extend_from_slice(&entry.crc.to_le_bytes());
+            header.extend_from_slice(&compressed_size.to_le_bytes());
+            header.extend_from_slice(&size.to_le_bytes());
+            header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            header.extend_from_slice(&[0; 6]); // comment length, disk number, internal attributes
+            header.extend_from_slice(&(entry.mode << 16).to_le_bytes());
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.extend_from_slice(entry.name.as_bytes());
+            header.extend_from_slice(&extra);
+            self.writer.write_all(&header)?;
+        }
+        let central_end = self.writer.count;
+        let central_size = central_end - central_start;
+        let count = self.entries.len() as u64;
+        let zip64 = count >= u16::MAX as u64 || central_start >= u32::MAX as u64 || central_size >= u32::MAX as u64;
+        let mut end = Vec::new();
+        if zip64 {
+            end.extend_from_slice(&ZIP64_END.to_le_bytes());
+            end.extend_from_slice(&44u64.to_le_bytes());
+            end.extend_from_slice(&(0x0300u16 | 45).to_le_bytes());
+            end.extend_from_slice(&45u16.to_le_bytes());
+            end.extend_from_slice(&[0; 8]); // disk numbers
+            end.extend_from_slice(&count.to_le_bytes());
+            end.extend_from_slice(&count.to_le_bytes());
+            end.extend_from_slice(&central_size.to_le_bytes());
+            end.extend_from_slice(&central_start.to_le_bytes());
+            end.extend_from_slice(&ZIP64_LOCATOR.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&central_end.to_le_bytes());
+            end.extend_from_slice(&1u32.to_le_bytes());
+        }
+        end.extend_from_slice(&END.to_le_bytes());
+        end.extend_from_slice(&[0; 4]); // disk numbers
+        let count = count.min(u16::MAX as u64) as u16;
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&(central_size.min(u32::MAX as u64) as u32).to_le_bytes());
+        end.extend_from_slice(&(central_start.min(u32::MAX as u64) as u32).to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        self.writer.write_all(&end)?;
+        self.writer.flush()?;
+        Ok(self.writer.inner)
+    }
+    fn local_header(&mut self, name: &str, method: u16, dos_time: (u16, u16), zip64: bool) -> io::Result<()> {
+        let extra: &[u8] = if zip64 {&[1, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]} else {&[]};
+        let sizes = if zip64 {u32::MAX} else {0};
+        let mut header = Vec::with_capacity(30 + name.len() + extra.len());
+        header.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        header.extend_from_slice(&(if zip64 {45u16} else {20u16}).to_le_bytes());
+        header.extend_from_slice(&FLAGS.to_le_bytes());
+        header.extend_from_slice(&method.to_le_bytes());
+        header.extend_from_slice(&dos_time.0.to_le_bytes());
+        header.extend_from_slice(&dos_time.1.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // crc, in the data descriptor
+        header.extend_from_slice(&sizes.to_le_bytes());
+        header.extend_from_slice(&sizes.to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        header.extend_from_slice(extra);
+        self.writer.write_all(&header)
+    }
+    fn data_descriptor(&mut self, crc: u32, compressed_size: u64, size: u64, zip64: bool) -> io::Result<()> {
+        let mut descriptor = Vec::with_capacity(24);
+        descriptor.extend_from_slice(&DATA_DESCRIPTOR.to_le_bytes());
+        descriptor.extend_from_slice(&crc.to_le_bytes());
+        if zip64 {
+            descriptor.extend_from_slice(&compressed_size.to_le_bytes());
+            descriptor.extend_from_slice(&size.to_le_bytes());
+        } else {
+            descriptor.extend_from_slice(&(compressed_size as u32).to_le_bytes());
+            descriptor.extend_from_slice(&(size as u32).to_le_bytes());
+        }
+        self.writer.write_all(&descriptor)
+    }
+}
+
+/// # MS-DOS time and date, as stored in zip headers
+/// Dates before 1980 can not be stored and become 1980-01-01.
+pub fn dos_time((year, month, day, seconds): (i64, i64, i64, u64)) -> (u16, u16) {
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = ((seconds / 3600) << 11) | ((seconds % 3600 / 60) << 5) | ((seconds % 60) / 2);
+    let date = (((year.min(2107) - 1980) << 9) | (month << 5) | day) as u16;
+    (time as u16, date)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn central_directory_offsets() {
+        let mut zip = ZipStream::new(Vec::new());
+        zip.add_dir("a/", (0, 33), 0o755).unwrap();
+        zip.add_file("a/b.txt", &mut "hello".as_bytes(), 5, (0, 33), 0o644).unwrap();
+        let bytes = zip.finish().unwrap();
+        let end = &bytes[bytes.len() - 22..];
+        assert_eq!(end[..4], END.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+        let central_size = u32::from_le_bytes([end[12], end[13], end[14], end[15]]) as usize;
+        let central_start = u32::from_le_bytes([end[16], end[17], end[18], end[19]]) as usize;
+        assert_eq!(central_start + central_size, bytes.len() - 22);
+        // the second central header starts right after the first one
+        let second = central_start + 46 + "a/".len();
+        assert_eq!(bytes[second..second + 4], CENTRAL_HEADER.to_le_bytes());
+        assert_eq!(dos_time((2021, 7, 10, 45296)), (0x645C, 0x52EA));
+    }
+
+    /// Writes more than 4 GiB, so it is only run on demand: `cargo test --release -- --ignored zip64`
+    #[test]
+    #[ignore]
+    fn zip64_with_unzip() {
+        let path = std::env::temp_dir().join(format!("happy_server_zip64_{}_{:?}.zip", std::process::id(), std::thread::current().id()));
+        let file = io::BufWriter::new(std::fs::File::create(&path).unwrap());
+        let mut zip = ZipStream::new(file);
+        for number in 0..70_000 {
+            zip.add_file(&format!("small/{}.txt", number), &mut "hello".as_bytes(), 5, (0, 33), 0o644).unwrap();
+        }
+        let size = 0x1_0000_0000 + 0x10_0000;
+        zip.add_file("large.bin", &mut io::repeat(0).take(size), size, (0, 33), 0o644).unwrap();
+        // written beyond 4 GiB, so the offset needs ZIP64 as well
+        zip.add_file("last.txt", &mut "last".as_bytes(), 4, (0, 33), 0o644).unwrap();
+        zip.finish().unwrap().flush().unwrap();
+        let status = std::process::Command::new("unzip").arg("-tqq").arg(&path).status();
+        std::fs::remove_file(&path).unwrap();
+        assert!(status.unwrap().success());
+    }
+}
@@ -0,0 +1,40 @@
+// Copyright (c) 2021 Daichi Aoki  
+// Released under the [MIT license](https://github.com/blz-soft/happy_server/blob/main/LICENSE)  
+
+use qrcode::{Color, QrCode};
+
+/// Width of the light margin around the code, in modules.
+/// The standard asks for 4, 2 is enough for phone cameras and saves terminal space.
+const QUIET_ZONE: usize = 2;
+
+/// # Render a QR code with Unicode half blocks
+/// Each character covers two rows of modules.
+/// `blocks` are the characters for (upper, lower) = (dark, dark), (dark, light), (light, dark), (light, light).
+/// Returns None if the data is too long for a QR code.
+pub fn render(data: &str, blocks: &[String; 4]) -> Option<String> {
+    let code = QrCode::new(data.as_bytes()).ok()?;
+    let width = code.width();
+    let colors = code.to_colors();
+    let size = width + QUIET_ZONE * 2;
+    let is_dark = |x: usize, y: usize| {
+        if x < QUIET_ZONE || y < QUIET_ZONE || x >= width + QUIET_ZONE || y >= width + QUIET_ZONE {
+            return false;
+        }
+        colors[(y - QUIET_ZONE) * width + (x - QUIET_ZONE)] == Color::Dark
+    };
+
+    let mut output = String::new();
+    for y in (0..size).step_by(2) {
+        for x in 0..size {
+            let block = match (is_dark(x, y), is_dark(x, y + 1)) {
+                (true, true) => &blocks[0],
+                (true, false) => &blocks[1],
+                (false, true) => &blocks[2],
+                (false, false) => &blocks[3],
+            };
+            output.push_str(block);
+        }
+        output.push('\n');
+    }
+    Some(output)
+}